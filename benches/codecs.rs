@@ -0,0 +1,95 @@
+// MIT License
+//
+// Copyright (c) 2019 Ankur Srivastava
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Compare the row oriented BatchMessage layout with the columnar one.
+//!
+//! Run with `cargo bench`, the payload sizes are printed before the timings.
+
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+use kafka_rust_example::{
+	codec::Codec,
+	generated::{BatchMessage, Message},
+};
+use std::collections::HashMap;
+
+const CODECS: [Codec; 2] = [Codec::Protobuf, Codec::Columnar];
+
+/// Build a batch as a busy host would publish it: a fixed set of metrics
+/// collected every second, with slowly changing values.
+fn batch(points: usize) -> BatchMessage {
+	let names = ["used-memory", "/dev/sda1", "/dev/sdb1", "/dev/nvme0n1p1"];
+	let mut labels = HashMap::new();
+	labels.insert("host".to_string(), "metrics-host-01".to_string());
+
+	let multiple_points = (0..points)
+		.map(|idx| {
+			let tick = (idx / names.len()) as i64;
+			Message {
+				timestamp: 1_600_000_000_000 + tick * 1000,
+				name: names[idx % names.len()].to_string(),
+				value: 16_000_000_000.0 + (tick % 7) as f32 * 4096.0,
+				labels: labels.clone(),
+			}
+		})
+		.collect();
+	BatchMessage { multiple_points }
+}
+
+fn bench_codecs(c: &mut Criterion) {
+	for points in [100, 10_000].iter() {
+		let batch = batch(*points);
+		for codec in CODECS.iter() {
+			let size = codec.encode(&batch).unwrap().len();
+			println!("{:?} payload for {} points: {} bytes", codec, points, size);
+		}
+	}
+
+	let mut group = c.benchmark_group("encode");
+	for points in [100, 10_000].iter() {
+		let batch = batch(*points);
+		for codec in CODECS.iter() {
+			group.bench_with_input(
+				BenchmarkId::new(format!("{:?}", codec), points),
+				&batch,
+				|b, batch| b.iter(|| codec.encode(black_box(batch)).unwrap()),
+			);
+		}
+	}
+	group.finish();
+
+	let mut group = c.benchmark_group("decode");
+	for points in [100, 10_000].iter() {
+		let batch = batch(*points);
+		for codec in CODECS.iter() {
+			let payload = codec.encode(&batch).unwrap();
+			group.bench_with_input(
+				BenchmarkId::new(format!("{:?}", codec), points),
+				&payload,
+				|b, payload| b.iter(|| codec.decode(black_box(payload)).unwrap()),
+			);
+		}
+	}
+	group.finish();
+}
+
+criterion_group!(benches, bench_codecs);
+criterion_main!(benches);
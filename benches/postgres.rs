@@ -0,0 +1,83 @@
+// MIT License
+//
+// Copyright (c) 2019 Ankur Srivastava
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Compare writing batches with a single INSERT of arrays against a binary COPY.
+//!
+//! Needs the postgres of `docker-compose up -d` with the migrations applied.
+//! Run with `cargo bench --bench postgres`, the rows are left in the table.
+
+use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion, Throughput};
+use kafka_rust_example::{
+	generated::{BatchMessage, Message},
+	postgres::DbClient,
+};
+use std::{
+	collections::HashMap,
+	sync::atomic::{AtomicI64, Ordering},
+};
+use tokio::runtime::Runtime;
+
+/// Timestamp of the next point, every point gets its own one so that the
+/// rows of the iterations don't collide.
+static NEXT_TIMESTAMP: AtomicI64 = AtomicI64::new(1_600_000_000);
+
+fn batch(points: usize) -> BatchMessage {
+	let mut labels = HashMap::new();
+	labels.insert("host".to_string(), "metrics-host-01".to_string());
+
+	let multiple_points = (0..points)
+		.map(|idx| Message {
+			timestamp: NEXT_TIMESTAMP.fetch_add(1, Ordering::Relaxed),
+			name: format!("metric-{}", idx % 16),
+			value: idx as f32,
+			labels: labels.clone(),
+		})
+		.collect();
+	BatchMessage { multiple_points }
+}
+
+fn bench_inserts(c: &mut Criterion) {
+	let runtime = Runtime::new().unwrap();
+	let client = || DbClient::new("localhost", "5432", "postgres", "password", "timeseries");
+	// Thresholds which force either path.
+	let paths = [("insert", usize::MAX), ("copy", 1)];
+
+	let mut group = c.benchmark_group("write");
+	group.sample_size(10);
+	for points in [100, 1_000, 10_000].iter() {
+		group.throughput(Throughput::Elements(*points as u64));
+		for (path, threshold) in paths.iter() {
+			let client = client().with_copy_threshold(*threshold);
+			group.bench_with_input(BenchmarkId::new(*path, points), points, |b, points| {
+				b.iter_batched(
+					|| batch(*points),
+					|batch| runtime.block_on(client.insert(&batch)).unwrap(),
+					BatchSize::LargeInput,
+				)
+			});
+		}
+	}
+	group.finish();
+}
+
+criterion_group!(benches, bench_inserts);
+criterion_main!(benches);
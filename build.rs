@@ -1,5 +1,9 @@
 fn main() {
-	prost_build::compile_protos(&["src/data/message.proto"], &["src/"]).unwrap();
+	prost_build::compile_protos(
+		&["src/data/message.proto", "src/data/otlp.proto"],
+		&["src/"],
+	)
+	.unwrap();
 
 	// ***** NOTE ******
 	// Uncomment this so as to copy the new file
@@ -12,4 +16,7 @@ fn main() {
 	// let tmp_generated_message = dst.join("messages.rs");
 	// let output_file = PathBuf::from("src/generated/messages.rs");
 	// fs::copy(tmp_generated_message, output_file).unwrap();
+	// let tmp_generated_otlp = dst.join("otlp.rs");
+	// let output_file = PathBuf::from("src/generated/otlp.rs");
+	// fs::copy(tmp_generated_otlp, output_file).unwrap();
 }
@@ -0,0 +1,2 @@
+-- Add migration script here
+ALTER TABLE metrics ADD COLUMN labels JSONB NOT NULL DEFAULT '{}'::jsonb;
\ No newline at end of file
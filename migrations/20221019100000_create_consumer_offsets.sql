@@ -0,0 +1,8 @@
+-- Add migration script here
+
+CREATE TABLE consumer_offsets (
+    topic TEXT NOT NULL,
+    partition INTEGER NOT NULL,
+    next_offset BIGINT NOT NULL,
+    PRIMARY KEY (topic, partition)
+);
@@ -0,0 +1,8 @@
+-- Add migration script here
+
+CREATE TABLE processed_batches (
+    batch_id UUID PRIMARY KEY,
+    processed_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX processed_batches_processed_at_idx ON processed_batches (processed_at);
@@ -0,0 +1,10 @@
+-- Add migration script here
+
+CREATE TABLE out_of_range_metrics (
+    timestamp TIMESTAMPTZ NOT NULL,
+    name TEXT NOT NULL,
+    value DOUBLE PRECISION NOT NULL,
+    labels JSONB NOT NULL DEFAULT '{}'::jsonb,
+    reason TEXT NOT NULL,
+    received_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
@@ -0,0 +1,9 @@
+-- Add migration script here
+
+-- Points with the same timestamp but another name or labels are different
+-- points, so key the table by all three. jsonb's text form is canonical, so
+-- equal labels hash equally.
+ALTER TABLE metrics
+    ADD COLUMN labels_hash TEXT GENERATED ALWAYS AS (md5(labels::text)) STORED;
+ALTER TABLE metrics DROP CONSTRAINT metrics_pkey;
+ALTER TABLE metrics ADD PRIMARY KEY (name, labels_hash, timestamp);
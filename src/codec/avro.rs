@@ -0,0 +1,171 @@
+// MIT License
+//
+// Copyright (c) 2019 Ankur Srivastava
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use crate::{
+	errors::AppError,
+	generated::{BatchMessage, Message},
+};
+use apache_avro::{from_value, Reader, Schema, Writer};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// Schema of a single metric point as written by publishers.
+///
+/// Every field added to this schema needs a default, so that payloads written
+/// with an older revision can still be resolved against it by the readers.
+const METRIC_SCHEMA: &str = r#"
+{
+	"type": "record",
+	"name": "Metric",
+	"namespace": "messages",
+	"fields": [
+		{"name": "timestamp", "type": "long"},
+		{"name": "name", "type": "string"},
+		{"name": "value", "type": "float"},
+		{"name": "labels", "type": {"type": "map", "values": "string"}, "default": {}}
+	]
+}
+"#;
+
+#[derive(Debug, Serialize, Deserialize)]
+struct AvroMetric {
+	timestamp: i64,
+	name: String,
+	value: f32,
+	#[serde(default)]
+	labels: HashMap<String, String>,
+}
+
+impl From<&Message> for AvroMetric {
+	fn from(message: &Message) -> Self {
+		AvroMetric {
+			timestamp: message.timestamp,
+			name: message.name.clone(),
+			value: message.value,
+			labels: message.labels.clone(),
+		}
+	}
+}
+
+impl From<AvroMetric> for Message {
+	fn from(metric: AvroMetric) -> Self {
+		Message {
+			timestamp: metric.timestamp,
+			name: metric.name,
+			value: metric.value,
+			labels: metric.labels,
+		}
+	}
+}
+
+/// Encode a BatchMessage as an avro object container.
+///
+/// The writer schema is stored in the container header, which lets
+/// consumers with a newer or older schema resolve the records.
+pub(crate) fn encode(batch: &BatchMessage) -> Result<Vec<u8>, AppError> {
+	let schema = Schema::parse_str(METRIC_SCHEMA)?;
+	let mut writer = Writer::new(&schema, Vec::new());
+	for message in batch.multiple_points.iter() {
+		writer.append_ser(AvroMetric::from(message))?;
+	}
+	Ok(writer.into_inner()?)
+}
+
+/// Decode an avro object container back to a BatchMessage.
+///
+/// Records are resolved from the writer schema in the container to the
+/// current METRIC_SCHEMA, filling in defaults for missing fields.
+pub(crate) fn decode(raw_data: &[u8]) -> Result<BatchMessage, AppError> {
+	let schema = Schema::parse_str(METRIC_SCHEMA)?;
+	let reader = Reader::with_schema(&schema, raw_data)?;
+
+	let mut batch = BatchMessage::default();
+	for value in reader {
+		let metric: AvroMetric = from_value(&value?)?;
+		batch.multiple_points.push(metric.into());
+	}
+	Ok(batch)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	/// First revision of the metric schema, before labels were added.
+	const METRIC_SCHEMA_V1: &str = r#"
+	{
+		"type": "record",
+		"name": "Metric",
+		"namespace": "messages",
+		"fields": [
+			{"name": "timestamp", "type": "long"},
+			{"name": "name", "type": "string"},
+			{"name": "value", "type": "float"}
+		]
+	}
+	"#;
+
+	#[derive(Serialize)]
+	struct AvroMetricV1 {
+		timestamp: i64,
+		name: String,
+		value: f32,
+	}
+
+	#[test]
+	fn test_decode_older_writer_schema() {
+		let schema = Schema::parse_str(METRIC_SCHEMA_V1).unwrap();
+		let mut writer = Writer::new(&schema, Vec::new());
+		writer
+			.append_ser(AvroMetricV1 {
+				timestamp: 1,
+				name: "used-memory".to_string(),
+				value: 42f32,
+			})
+			.unwrap();
+		let raw_data = writer.into_inner().unwrap();
+
+		let batch = decode(&raw_data).unwrap();
+		assert_eq!(batch.multiple_points.len(), 1);
+
+		let message = &batch.multiple_points[0];
+		assert_eq!(message.name, "used-memory");
+		assert_eq!(message.timestamp, 1);
+		assert!(message.labels.is_empty());
+	}
+
+	#[test]
+	fn test_labels_roundtrip() {
+		let mut labels = HashMap::new();
+		labels.insert("host".to_string(), "localhost".to_string());
+		let batch = BatchMessage {
+			multiple_points: vec![Message {
+				name: "used-memory".to_string(),
+				labels,
+				..Default::default()
+			}],
+		};
+
+		let decoded = decode(&encode(&batch).unwrap()).unwrap();
+		assert_eq!(decoded, batch);
+	}
+}
@@ -0,0 +1,342 @@
+// MIT License
+//
+// Copyright (c) 2019 Ankur Srivastava
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use crate::{
+	errors::AppError,
+	generated::{BatchMessage, ColumnarBatchMessage, LabelSet, Message},
+};
+use std::collections::{BTreeMap, HashMap};
+
+/// Convert a BatchMessage to its columnar layout.
+///
+/// Names and label sets are stored once in a dictionary and referenced by
+/// index, timestamps are delta-of-delta encoded and values are compressed by
+/// XOR-ing them with their predecessor, as described in the Gorilla paper.
+pub(crate) fn encode(batch: &BatchMessage) -> ColumnarBatchMessage {
+	let mut columnar = ColumnarBatchMessage::default();
+	let mut name_ids = HashMap::new();
+	let mut label_set_ids = HashMap::new();
+
+	for message in batch.multiple_points.iter() {
+		let name_id = *name_ids.entry(message.name.as_str()).or_insert_with(|| {
+			columnar.names.push(message.name.clone());
+			columnar.names.len() as u32 - 1
+		});
+		columnar.name_ids.push(name_id);
+
+		let labels: BTreeMap<&str, &str> = message
+			.labels
+			.iter()
+			.map(|(key, value)| (key.as_str(), value.as_str()))
+			.collect();
+		let label_set_id = *label_set_ids.entry(labels).or_insert_with(|| {
+			columnar.label_sets.push(LabelSet {
+				labels: message.labels.clone(),
+			});
+			columnar.label_sets.len() as u32 - 1
+		});
+		columnar.label_set_ids.push(label_set_id);
+	}
+
+	columnar.timestamps = encode_timestamps(batch.multiple_points.iter().map(|m| m.timestamp));
+	columnar.values = encode_values(batch.multiple_points.iter().map(|m| m.value));
+	columnar
+}
+
+/// Convert a columnar batch back to a BatchMessage.
+pub(crate) fn decode(columnar: ColumnarBatchMessage) -> Result<BatchMessage, AppError> {
+	let count = columnar.name_ids.len();
+	if columnar.label_set_ids.len() != count || columnar.timestamps.len() != count {
+		return Err(AppError::Columnar("columns differ in length".to_string()));
+	}
+	let timestamps = decode_timestamps(&columnar.timestamps);
+	let values = decode_values(&columnar.values, count)
+		.ok_or_else(|| AppError::Columnar("values are truncated".to_string()))?;
+
+	let mut batch = BatchMessage::default();
+	for idx in 0..count {
+		let name = columnar
+			.names
+			.get(columnar.name_ids[idx] as usize)
+			.ok_or_else(|| AppError::Columnar("name id out of range".to_string()))?;
+		let label_set = columnar
+			.label_sets
+			.get(columnar.label_set_ids[idx] as usize)
+			.ok_or_else(|| AppError::Columnar("label set id out of range".to_string()))?;
+		batch.multiple_points.push(Message {
+			timestamp: timestamps[idx],
+			name: name.clone(),
+			value: values[idx],
+			labels: label_set.labels.clone(),
+		});
+	}
+	Ok(batch)
+}
+
+/// Store the first timestamp, then the first delta and from there on only
+/// the change in delta, which is zero for points collected at a fixed interval.
+fn encode_timestamps(timestamps: impl Iterator<Item = i64>) -> Vec<i64> {
+	let mut encoded = vec![];
+	let (mut previous, mut previous_delta) = (0i64, 0i64);
+	for (idx, timestamp) in timestamps.enumerate() {
+		let delta = timestamp.wrapping_sub(previous);
+		encoded.push(match idx {
+			0 => timestamp,
+			1 => delta,
+			_ => delta.wrapping_sub(previous_delta),
+		});
+		previous = timestamp;
+		previous_delta = delta;
+	}
+	encoded
+}
+
+fn decode_timestamps(encoded: &[i64]) -> Vec<i64> {
+	let mut timestamps = Vec::with_capacity(encoded.len());
+	let (mut previous, mut delta) = (0i64, 0i64);
+	for (idx, value) in encoded.iter().enumerate() {
+		let timestamp = match idx {
+			0 => *value,
+			1 => {
+				delta = *value;
+				previous.wrapping_add(delta)
+			}
+			_ => {
+				delta = delta.wrapping_add(*value);
+				previous.wrapping_add(delta)
+			}
+		};
+		timestamps.push(timestamp);
+		previous = timestamp;
+	}
+	timestamps
+}
+
+/// Gorilla XOR compression of the values.
+///
+/// The first value is stored as is. For every following value it is XOR-ed
+/// with its predecessor and
+/// - `0` is written if both are equal,
+/// - `10` followed by the meaningful bits if they fit in the previous window
+///   of leading and trailing zeros,
+/// - `11` followed by 5 bits of leading zeros, 5 bits of meaningful bits
+///   length minus one and the meaningful bits otherwise.
+fn encode_values(values: impl Iterator<Item = f32>) -> Vec<u8> {
+	let mut writer = BitWriter::default();
+	let mut previous: Option<u32> = None;
+	let mut window: Option<(u32, u32)> = None;
+
+	for value in values {
+		let bits = value.to_bits();
+		let xor = match previous {
+			Some(previous) => bits ^ previous,
+			None => {
+				writer.write_bits(bits, 32);
+				previous = Some(bits);
+				continue;
+			}
+		};
+		previous = Some(bits);
+
+		if xor == 0 {
+			writer.write_bit(false);
+			continue;
+		}
+		writer.write_bit(true);
+
+		let (leading, trailing) = (xor.leading_zeros(), xor.trailing_zeros());
+		match window {
+			Some((window_leading, window_trailing))
+				if leading >= window_leading && trailing >= window_trailing =>
+			{
+				writer.write_bit(false);
+				writer.write_bits(
+					xor >> window_trailing,
+					32 - window_leading - window_trailing,
+				);
+			}
+			_ => {
+				let meaningful = 32 - leading - trailing;
+				writer.write_bit(true);
+				writer.write_bits(leading, 5);
+				writer.write_bits(meaningful - 1, 5);
+				writer.write_bits(xor >> trailing, meaningful);
+				window = Some((leading, trailing));
+			}
+		}
+	}
+	writer.bytes
+}
+
+fn decode_values(bytes: &[u8], count: usize) -> Option<Vec<f32>> {
+	let mut reader = BitReader::new(bytes);
+	let mut values = Vec::with_capacity(count);
+	let mut previous = 0u32;
+	let (mut leading, mut trailing) = (0u32, 0u32);
+
+	for idx in 0..count {
+		let bits = if idx == 0 {
+			reader.read_bits(32)?
+		} else if !reader.read_bit()? {
+			previous
+		} else {
+			if reader.read_bit()? {
+				leading = reader.read_bits(5)?;
+				let meaningful = reader.read_bits(5)? + 1;
+				trailing = 32u32.checked_sub(leading + meaningful)?;
+			}
+			let meaningful = 32 - leading - trailing;
+			previous ^ (reader.read_bits(meaningful)? << trailing)
+		};
+		values.push(f32::from_bits(bits));
+		previous = bits;
+	}
+	Some(values)
+}
+
+#[derive(Default)]
+struct BitWriter {
+	bytes: Vec<u8>,
+	/// Number of bits used in the last byte, 0 meaning it is full.
+	used: u8,
+}
+
+impl BitWriter {
+	fn write_bit(&mut self, bit: bool) {
+		if self.used == 0 {
+			self.bytes.push(0);
+		}
+		if bit {
+			*self.bytes.last_mut().unwrap() |= 0x80 >> self.used;
+		}
+		self.used = (self.used + 1) % 8;
+	}
+
+	/// Write the lowest `count` bits of `value`, most significant first.
+	fn write_bits(&mut self, value: u32, count: u32) {
+		for shift in (0..count).rev() {
+			self.write_bit((value >> shift) & 1 == 1);
+		}
+	}
+}
+
+struct BitReader<'a> {
+	bytes: &'a [u8],
+	position: usize,
+}
+
+impl<'a> BitReader<'a> {
+	fn new(bytes: &'a [u8]) -> Self {
+		BitReader { bytes, position: 0 }
+	}
+
+	fn read_bit(&mut self) -> Option<bool> {
+		let byte = self.bytes.get(self.position / 8)?;
+		let bit = byte & (0x80 >> (self.position % 8)) != 0;
+		self.position += 1;
+		Some(bit)
+	}
+
+	fn read_bits(&mut self, count: u32) -> Option<u32> {
+		let mut value = 0u32;
+		for _ in 0..count {
+			value = (value << 1) | self.read_bit()? as u32;
+		}
+		Some(value)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use prost::Message as PMessage;
+
+	fn batch() -> BatchMessage {
+		let mut points = vec![];
+		for tick in 0..50 {
+			for (idx, name) in ["used-memory", "/dev/sda1", "/dev/sdb1"].iter().enumerate() {
+				let mut labels = HashMap::new();
+				labels.insert("host".to_string(), format!("host-{}", idx % 2));
+				points.push(Message {
+					timestamp: 1_600_000_000_000 + tick * 1000 + (tick % 3),
+					name: name.to_string(),
+					value: 1024.0 * (idx as f32 + 1.0) + (tick % 5) as f32,
+					labels,
+				});
+			}
+		}
+		BatchMessage {
+			multiple_points: points,
+		}
+	}
+
+	#[test]
+	fn test_roundtrip() {
+		let batch = batch();
+		let columnar = encode(&batch);
+		assert_eq!(columnar.names.len(), 3);
+		assert_eq!(columnar.label_sets.len(), 2);
+		assert_eq!(decode(columnar).unwrap(), batch);
+	}
+
+	#[test]
+	fn test_roundtrip_special_values() {
+		let values = vec![
+			0.0,
+			-0.0,
+			f32::MAX,
+			f32::MIN_POSITIVE,
+			f32::INFINITY,
+			1.5,
+			1.5,
+		];
+		let decoded = decode_values(&encode_values(values.iter().cloned()), values.len()).unwrap();
+		let to_bits = |values: &[f32]| values.iter().map(|v| v.to_bits()).collect::<Vec<_>>();
+		assert_eq!(to_bits(&decoded), to_bits(&values));
+
+		let timestamps = vec![i64::MIN, i64::MAX, 0, -1];
+		let encoded = encode_timestamps(timestamps.iter().cloned());
+		assert_eq!(decode_timestamps(&encoded), timestamps);
+	}
+
+	#[test]
+	fn test_smaller_than_batch_message() {
+		let batch = batch();
+		let columnar = encode(&batch);
+		assert!(columnar.encoded_len() * 2 < batch.encoded_len());
+	}
+
+	#[test]
+	fn test_decode_corrupt() {
+		let mut columnar = encode(&batch());
+		columnar.values.truncate(4);
+		assert!(decode(columnar).is_err());
+
+		let mut columnar = encode(&batch());
+		columnar.name_ids[0] = 42;
+		assert!(decode(columnar).is_err());
+
+		let mut columnar = encode(&batch());
+		columnar.timestamps.pop();
+		assert!(decode(columnar).is_err());
+	}
+}
@@ -0,0 +1,308 @@
+// MIT License
+//
+// Copyright (c) 2019 Ankur Srivastava
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use crate::{
+	errors::AppError,
+	generated::{BatchMessage, Message},
+};
+use chrono::prelude::*;
+use log::warn;
+use std::{collections::HashMap, fmt::Write};
+
+/// Name of the field which maps onto `Message.value`.
+const VALUE_FIELD: &str = "value";
+
+const NANOS_PER_MILLI: i64 = 1_000_000;
+
+/// Encode a BatchMessage as influx line protocol, one line per point.
+///
+/// The name becomes the measurement, labels become tags and the value is
+/// written to a single `value` field. Points with a NaN or infinite value
+/// can't be represented and are skipped. Timestamps beyond the range of
+/// nanoseconds in an i64, i.e. after 2262, fail the whole batch.
+pub(crate) fn encode(batch: &BatchMessage) -> Result<String, AppError> {
+	let mut lines = String::new();
+	for message in batch.multiple_points.iter() {
+		if !message.value.is_finite() {
+			warn!(
+				"Skipping non finite value for {} in line protocol",
+				message.name
+			);
+			continue;
+		}
+
+		let timestamp = message
+			.timestamp
+			.checked_mul(NANOS_PER_MILLI)
+			.ok_or_else(|| {
+				AppError::LineProtocol(format!(
+					"timestamp {} of {} is out of range",
+					message.timestamp, message.name
+				))
+			})?;
+
+		lines.push_str(&escape(&message.name, &[',', ' ']));
+
+		let mut tags: Vec<_> = message.labels.iter().collect();
+		tags.sort();
+		for (key, value) in tags {
+			lines.push(',');
+			lines.push_str(&escape(key, &[',', '=', ' ']));
+			lines.push('=');
+			lines.push_str(&escape(value, &[',', '=', ' ']));
+		}
+
+		// Writing to a String can't fail.
+		let _ = writeln!(lines, " {}={} {}", VALUE_FIELD, message.value, timestamp);
+	}
+	Ok(lines)
+}
+
+/// Decode influx line protocol back to a BatchMessage.
+///
+/// Every numeric or boolean field becomes a point of its own. The `value`
+/// field is named after the measurement, any other field is named
+/// `<measurement>_<field>`. String fields are ignored. Lines without a
+/// timestamp get the current time.
+pub(crate) fn decode(raw_data: &[u8]) -> Result<BatchMessage, AppError> {
+	let text = std::str::from_utf8(raw_data)
+		.map_err(|e| AppError::LineProtocol(format!("payload is not utf-8: {}", e)))?;
+
+	let mut batch = BatchMessage::default();
+	for line in text.lines() {
+		let line = line.trim_start();
+		if line.is_empty() || line.starts_with('#') {
+			continue;
+		}
+		batch.multiple_points.extend(parse_line(line)?);
+	}
+	Ok(batch)
+}
+
+/// Parse a single line of line protocol into one message per field.
+fn parse_line(line: &str) -> Result<Vec<Message>, AppError> {
+	let err = |reason: &str| AppError::LineProtocol(format!("{}: {}", reason, line));
+
+	// Quotes only have a meaning in the field set, so the series is split
+	// off first and the remainder is split with quoted strings in mind.
+	let series = split_unescaped(line, ' ', false)[0];
+	let sections = split_unescaped(line[series.len()..].trim_start(), ' ', true);
+	let (fields, timestamp) = match sections.as_slice() {
+		[fields] => (*fields, None),
+		[fields, timestamp] => (*fields, Some(*timestamp)),
+		_ => return Err(err("expected a field set and an optional timestamp")),
+	};
+
+	let mut series = split_unescaped(series, ',', false).into_iter();
+	let measurement = unescape(series.next().unwrap_or_default());
+	if measurement.is_empty() {
+		return Err(err("missing measurement"));
+	}
+
+	let mut labels = HashMap::new();
+	for tag in series {
+		match split_unescaped(tag, '=', false).as_slice() {
+			[key, value] if !key.is_empty() && !value.is_empty() => {
+				labels.insert(unescape(key), unescape(value));
+			}
+			_ => return Err(err("invalid tag")),
+		}
+	}
+
+	let timestamp = match timestamp {
+		Some(timestamp) => {
+			timestamp
+				.parse::<i64>()
+				.map_err(|_| err("invalid timestamp"))?
+				/ NANOS_PER_MILLI
+		}
+		None => Utc::now().timestamp_millis(),
+	};
+
+	let mut messages = vec![];
+	for field in split_unescaped(fields, ',', true) {
+		let (key, value) = match split_unescaped(field, '=', true).as_slice() {
+			[key, value] if !key.is_empty() && !value.is_empty() => (unescape(key), *value),
+			_ => return Err(err("invalid field")),
+		};
+		let value = match parse_field_value(value) {
+			Some(FieldValue::Number(value)) => value,
+			Some(FieldValue::Text) => continue,
+			None => return Err(err("invalid field value")),
+		};
+		let name = if key == VALUE_FIELD {
+			measurement.clone()
+		} else {
+			format!("{}_{}", measurement, key)
+		};
+		messages.push(Message {
+			timestamp,
+			name,
+			value,
+			labels: labels.clone(),
+		});
+	}
+
+	Ok(messages)
+}
+
+enum FieldValue {
+	Number(f32),
+	Text,
+}
+
+/// Parse a field value: floats, `i`/`u` suffixed integers, booleans and strings.
+fn parse_field_value(value: &str) -> Option<FieldValue> {
+	if value.len() >= 2 && value.starts_with('"') && value.ends_with('"') {
+		return Some(FieldValue::Text);
+	}
+	let number = match value {
+		"t" | "T" | "true" | "True" | "TRUE" => 1.0,
+		"f" | "F" | "false" | "False" | "FALSE" => 0.0,
+		_ if value.ends_with('i') => value[..value.len() - 1].parse::<i64>().ok()? as f32,
+		_ if value.ends_with('u') => value[..value.len() - 1].parse::<u64>().ok()? as f32,
+		_ => value.parse::<f32>().ok()?,
+	};
+	Some(FieldValue::Number(number))
+}
+
+/// Split `input` on every `delimiter` which is not escaped by a backslash
+/// and, if `quoted` is set, not inside a double quoted string.
+fn split_unescaped(input: &str, delimiter: char, quoted: bool) -> Vec<&str> {
+	let mut parts = vec![];
+	let mut start = 0;
+	let mut escaped = false;
+	let mut in_quotes = false;
+	for (idx, ch) in input.char_indices() {
+		if escaped {
+			escaped = false;
+		} else if ch == '\\' {
+			escaped = true;
+		} else if quoted && ch == '"' {
+			in_quotes = !in_quotes;
+		} else if ch == delimiter && !in_quotes {
+			parts.push(&input[start..idx]);
+			start = idx + ch.len_utf8();
+		}
+	}
+	parts.push(&input[start..]);
+	parts
+}
+
+/// Prefix every occurrence of the `special` characters with a backslash.
+fn escape(input: &str, special: &[char]) -> String {
+	let mut escaped = String::with_capacity(input.len());
+	for ch in input.chars() {
+		if special.contains(&ch) {
+			escaped.push('\\');
+		}
+		escaped.push(ch);
+	}
+	escaped
+}
+
+/// Drop the backslash in front of an escaped character.
+fn unescape(input: &str) -> String {
+	let mut unescaped = String::with_capacity(input.len());
+	let mut chars = input.chars().peekable();
+	while let Some(ch) = chars.next() {
+		match (ch, chars.peek()) {
+			('\\', Some(',')) | ('\\', Some('=')) | ('\\', Some(' ')) | ('\\', Some('"')) => {}
+			_ => unescaped.push(ch),
+		}
+	}
+	unescaped
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_parse_telegraf_line() {
+		let line = "cpu,cpu=cpu-total,host=my\\ host usage_idle=90.5,usage_user=5i,up=true,\
+		            note=\"a, b\" 1556813561098000000";
+		let batch = decode(line.as_bytes()).unwrap();
+		assert_eq!(batch.multiple_points.len(), 3);
+
+		let message = &batch.multiple_points[0];
+		assert_eq!(message.name, "cpu_usage_idle");
+		assert_eq!(message.value, 90.5);
+		assert_eq!(message.timestamp, 1556813561098);
+		assert_eq!(message.labels["host"], "my host");
+		assert_eq!(message.labels["cpu"], "cpu-total");
+
+		assert_eq!(batch.multiple_points[1].value, 5.0);
+		assert_eq!(batch.multiple_points[2].name, "cpu_up");
+		assert_eq!(batch.multiple_points[2].value, 1.0);
+	}
+
+	#[test]
+	fn test_parse_escaped_measurement() {
+		let batch = decode(b"disk\\,io\\ time value=1 1000000\n\n# comment\n").unwrap();
+		assert_eq!(batch.multiple_points.len(), 1);
+		assert_eq!(batch.multiple_points[0].name, "disk,io time");
+		assert_eq!(batch.multiple_points[0].timestamp, 1);
+	}
+
+	#[test]
+	fn test_parse_invalid_lines() {
+		assert!(decode(b"cpu").is_err());
+		assert!(decode(b"cpu,host value=1").is_err());
+		assert!(decode(b"cpu value=abc").is_err());
+		assert!(decode(b"cpu value=1 now").is_err());
+	}
+
+	#[test]
+	fn test_encode_decode() {
+		let mut labels = HashMap::new();
+		labels.insert("mount point".to_string(), "/a,b=c".to_string());
+		let batch = BatchMessage {
+			multiple_points: vec![Message {
+				timestamp: 1556813561098,
+				name: "disk available".to_string(),
+				value: 42.5,
+				labels,
+			}],
+		};
+
+		let lines = encode(&batch).unwrap();
+		assert_eq!(
+			lines,
+			"disk\\ available,mount\\ point=/a\\,b\\=c value=42.5 1556813561098000000\n"
+		);
+		assert_eq!(decode(lines.as_bytes()).unwrap(), batch);
+	}
+
+	#[test]
+	fn test_encode_out_of_range_timestamp() {
+		let batch = BatchMessage {
+			multiple_points: vec![Message {
+				timestamp: i64::MAX / 1000,
+				name: "far future".to_string(),
+				value: 1.0,
+				labels: HashMap::new(),
+			}],
+		};
+		assert!(matches!(encode(&batch), Err(AppError::LineProtocol(_))));
+	}
+}
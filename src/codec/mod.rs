@@ -0,0 +1,196 @@
+// MIT License
+//
+// Copyright (c) 2019 Ankur Srivastava
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+mod avro;
+mod columnar;
+mod line_protocol;
+mod otlp;
+
+use crate::{
+	errors::AppError,
+	generated::{BatchMessage, ColumnarBatchMessage},
+};
+use prost::{bytes::BytesMut, Message as PMessage};
+use serde::Deserialize;
+use std::str;
+
+/// Name of the kafka header which carries the codec a payload was encoded with.
+pub const CONTENT_TYPE_HEADER: &str = "content-type";
+
+/// Wire formats a BatchMessage can be encoded to or decoded from.
+#[derive(Deserialize, Debug, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Codec {
+	/// Protobuf encoded BatchMessage, see `data/message.proto`.
+	#[default]
+	Protobuf,
+
+	/// Avro object container, which carries its writer schema along.
+	Avro,
+
+	/// Influx line protocol, as written by telegraf and other agents.
+	LineProtocol,
+
+	/// OTLP metrics, as written by the OpenTelemetry collector. Decode only.
+	Otlp,
+
+	/// Protobuf encoded ColumnarBatchMessage, a compact layout for large batches.
+	Columnar,
+}
+
+impl Codec {
+	/// Value of the content-type header for payloads of this codec.
+	pub fn content_type(&self) -> &'static str {
+		match self {
+			Codec::Protobuf => "application/x-protobuf",
+			Codec::Avro => "avro/binary",
+			Codec::LineProtocol => "text/x-influx-line-protocol",
+			Codec::Otlp => "application/x-otlp-protobuf",
+			Codec::Columnar => "application/x-columnar-protobuf",
+		}
+	}
+
+	/// Find the codec belonging to a content-type header value.
+	pub fn from_content_type(value: &[u8]) -> Option<Codec> {
+		match str::from_utf8(value).ok()? {
+			"application/x-protobuf" => Some(Codec::Protobuf),
+			"avro/binary" => Some(Codec::Avro),
+			"text/x-influx-line-protocol" => Some(Codec::LineProtocol),
+			"application/x-otlp-protobuf" => Some(Codec::Otlp),
+			"application/x-columnar-protobuf" => Some(Codec::Columnar),
+			_ => None,
+		}
+	}
+
+	/// Check whether batches can be encoded with this codec. OTLP payloads
+	/// are only accepted from other publishers.
+	///
+	/// # Examples
+	/// Basic usage:
+	///
+	/// ```rust norun
+	/// config.kafka_codec.check_encodable()?;
+	/// ```
+	pub fn check_encodable(&self) -> Result<(), AppError> {
+		match self {
+			Codec::Otlp => Err(AppError::UnsupportedEncoding(*self)),
+			_ => Ok(()),
+		}
+	}
+
+	/// Encode a BatchMessage to bytes.
+	///
+	/// # Examples
+	/// Basic usage:
+	///
+	/// ```rust norun
+	/// let buffer = Codec::Avro.encode(&BatchMessage::default()).unwrap();
+	/// ```
+	pub fn encode(&self, batch: &BatchMessage) -> Result<BytesMut, AppError> {
+		match self {
+			Codec::Protobuf => {
+				let mut buffer = BytesMut::with_capacity(batch.encoded_len());
+				batch.encode(&mut buffer)?;
+				Ok(buffer)
+			}
+			Codec::Avro => Ok(BytesMut::from(&avro::encode(batch)?[..])),
+			Codec::LineProtocol => Ok(BytesMut::from(line_protocol::encode(batch)?.as_bytes())),
+			Codec::Otlp => Err(AppError::UnsupportedEncoding(*self)),
+			Codec::Columnar => {
+				let columnar = columnar::encode(batch);
+				let mut buffer = BytesMut::with_capacity(columnar.encoded_len());
+				columnar.encode(&mut buffer)?;
+				Ok(buffer)
+			}
+		}
+	}
+
+	/// Decode bytes back to a BatchMessage.
+	///
+	/// # Examples
+	/// Basic usage:
+	///
+	/// ```rust norun
+	/// let batch = Codec::Avro.decode(&raw_data).unwrap();
+	/// ```
+	pub fn decode(&self, raw_data: &[u8]) -> Result<BatchMessage, AppError> {
+		match self {
+			Codec::Protobuf => Ok(BatchMessage::decode(raw_data)?),
+			Codec::Avro => avro::decode(raw_data),
+			Codec::LineProtocol => line_protocol::decode(raw_data),
+			Codec::Otlp => otlp::decode(raw_data),
+			Codec::Columnar => columnar::decode(ColumnarBatchMessage::decode(raw_data)?),
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::metrics::MetricsGenerator;
+
+	#[test]
+	fn test_content_type_roundtrip() {
+		for codec in &[
+			Codec::Protobuf,
+			Codec::Avro,
+			Codec::LineProtocol,
+			Codec::Otlp,
+			Codec::Columnar,
+		] {
+			let content_type = codec.content_type();
+			assert_eq!(
+				Codec::from_content_type(content_type.as_bytes()),
+				Some(*codec)
+			);
+		}
+		assert_eq!(Codec::from_content_type(b"text/plain"), None);
+	}
+
+	#[test]
+	fn test_encode_decode() {
+		let batch = BatchMessage {
+			multiple_points: vec![
+				MetricsGenerator::create_metrics("user1".to_string(), 321f32, Some(1)),
+				MetricsGenerator::create_metrics("user2".to_string(), 123f32, Some(2)),
+			],
+		};
+
+		for codec in &[
+			Codec::Protobuf,
+			Codec::Avro,
+			Codec::LineProtocol,
+			Codec::Columnar,
+		] {
+			let buffer = codec.encode(&batch).unwrap();
+			let decoded = codec.decode(&buffer).unwrap();
+			assert_eq!(decoded, batch, "Roundtrip failed for {:?}", codec);
+		}
+		assert!(Codec::Otlp.encode(&batch).is_err());
+	}
+
+	#[test]
+	fn test_check_encodable() {
+		assert!(Codec::Otlp.check_encodable().is_err());
+		assert!(Codec::Columnar.check_encodable().is_ok());
+	}
+}
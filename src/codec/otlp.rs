@@ -0,0 +1,308 @@
+// MIT License
+//
+// Copyright (c) 2019 Ankur Srivastava
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use crate::{
+	errors::AppError,
+	generated::{
+		otlp::{
+			any_value, metric::Data, number_data_point, ExportMetricsServiceRequest,
+			HistogramDataPoint, KeyValue, NumberDataPoint,
+		},
+		BatchMessage, Message,
+	},
+};
+use chrono::prelude::*;
+use prost::Message as PMessage;
+use std::collections::HashMap;
+
+const NANOS_PER_MILLI: u64 = 1_000_000;
+
+/// Decode an OTLP ExportMetricsServiceRequest, as written by the kafka
+/// exporter of the OpenTelemetry collector, to a BatchMessage.
+///
+/// Gauges and sums become one point per data point. Histograms are flattened
+/// the way prometheus does it: `<name>_count`, `<name>_sum` and a cumulative
+/// `<name>_bucket` per bound with an `le` label. Resource attributes are
+/// turned into labels, data point attributes take precedence over them.
+pub(crate) fn decode(raw_data: &[u8]) -> Result<BatchMessage, AppError> {
+	let request = ExportMetricsServiceRequest::decode(raw_data)?;
+
+	let mut batch = BatchMessage::default();
+	for resource_metrics in request.resource_metrics.iter() {
+		let resource_labels = resource_metrics
+			.resource
+			.as_ref()
+			.map(|resource| to_labels(&resource.attributes))
+			.unwrap_or_default();
+
+		for metric in resource_metrics
+			.scope_metrics
+			.iter()
+			.flat_map(|scope| scope.metrics.iter())
+		{
+			let points = &mut batch.multiple_points;
+			match &metric.data {
+				Some(Data::Gauge(gauge)) => {
+					flatten_numbers(&metric.name, &gauge.data_points, &resource_labels, points)
+				}
+				Some(Data::Sum(sum)) => {
+					flatten_numbers(&metric.name, &sum.data_points, &resource_labels, points)
+				}
+				Some(Data::Histogram(histogram)) => flatten_histograms(
+					&metric.name,
+					&histogram.data_points,
+					&resource_labels,
+					points,
+				),
+				None => {}
+			}
+		}
+	}
+	Ok(batch)
+}
+
+fn flatten_numbers(
+	name: &str,
+	data_points: &[NumberDataPoint],
+	resource_labels: &HashMap<String, String>,
+	points: &mut Vec<Message>,
+) {
+	for data_point in data_points {
+		let value = match data_point.value {
+			Some(number_data_point::Value::AsDouble(value)) => value as f32,
+			Some(number_data_point::Value::AsInt(value)) => value as f32,
+			None => continue,
+		};
+		points.push(Message {
+			timestamp: to_millis(data_point.time_unix_nano),
+			name: name.to_string(),
+			value,
+			labels: merge_labels(resource_labels, &data_point.attributes),
+		});
+	}
+}
+
+fn flatten_histograms(
+	name: &str,
+	data_points: &[HistogramDataPoint],
+	resource_labels: &HashMap<String, String>,
+	points: &mut Vec<Message>,
+) {
+	for data_point in data_points {
+		let timestamp = to_millis(data_point.time_unix_nano);
+		let labels = merge_labels(resource_labels, &data_point.attributes);
+		let point = |name: String, value: f32, labels: HashMap<String, String>| Message {
+			timestamp,
+			name,
+			value,
+			labels,
+		};
+
+		points.push(point(
+			format!("{}_count", name),
+			data_point.count as f32,
+			labels.clone(),
+		));
+		points.push(point(
+			format!("{}_sum", name),
+			data_point.sum as f32,
+			labels.clone(),
+		));
+
+		// There is one more bucket than there are bounds, the last one being +Inf.
+		let mut cumulative = 0;
+		for (idx, count) in data_point.bucket_counts.iter().enumerate() {
+			cumulative += count;
+			let bound = data_point
+				.explicit_bounds
+				.get(idx)
+				.map(|bound| bound.to_string())
+				.unwrap_or_else(|| "+Inf".to_string());
+			let mut labels = labels.clone();
+			labels.insert("le".to_string(), bound);
+			points.push(point(format!("{}_bucket", name), cumulative as f32, labels));
+		}
+	}
+}
+
+/// Convert nanoseconds since epoch to milliseconds, an unset time means now.
+fn to_millis(time_unix_nano: u64) -> i64 {
+	if time_unix_nano == 0 {
+		return Utc::now().timestamp_millis();
+	}
+	(time_unix_nano / NANOS_PER_MILLI) as i64
+}
+
+fn merge_labels(
+	resource_labels: &HashMap<String, String>,
+	attributes: &[KeyValue],
+) -> HashMap<String, String> {
+	let mut labels = resource_labels.clone();
+	labels.extend(to_labels(attributes));
+	labels
+}
+
+/// Turn attributes into labels, skipping the ones without a scalar value.
+fn to_labels(attributes: &[KeyValue]) -> HashMap<String, String> {
+	attributes
+		.iter()
+		.filter_map(|attribute| {
+			let value = match attribute.value.as_ref()?.value.as_ref()? {
+				any_value::Value::StringValue(value) => value.clone(),
+				any_value::Value::BoolValue(value) => value.to_string(),
+				any_value::Value::IntValue(value) => value.to_string(),
+				any_value::Value::DoubleValue(value) => value.to_string(),
+			};
+			Some((attribute.key.clone(), value))
+		})
+		.collect()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::generated::otlp::{
+		AnyValue, Gauge, Histogram, Metric, Resource, ResourceMetrics, ScopeMetrics, Sum,
+	};
+
+	fn key_value(key: &str, value: any_value::Value) -> KeyValue {
+		KeyValue {
+			key: key.to_string(),
+			value: Some(AnyValue { value: Some(value) }),
+		}
+	}
+
+	fn number(time_unix_nano: u64, value: number_data_point::Value) -> NumberDataPoint {
+		NumberDataPoint {
+			attributes: vec![key_value(
+				"state",
+				any_value::Value::StringValue("used".to_string()),
+			)],
+			time_unix_nano,
+			value: Some(value),
+		}
+	}
+
+	fn request(metrics: Vec<Metric>) -> Vec<u8> {
+		let request = ExportMetricsServiceRequest {
+			resource_metrics: vec![ResourceMetrics {
+				resource: Some(Resource {
+					attributes: vec![
+						key_value(
+							"service.name",
+							any_value::Value::StringValue("api".to_string()),
+						),
+						key_value("replica", any_value::Value::IntValue(2)),
+					],
+				}),
+				scope_metrics: vec![ScopeMetrics { metrics }],
+			}],
+		};
+		request.encode_to_vec()
+	}
+
+	#[test]
+	fn test_decode_gauge_and_sum() {
+		let raw_data = request(vec![
+			Metric {
+				name: "system.memory.usage".to_string(),
+				data: Some(Data::Gauge(Gauge {
+					data_points: vec![number(2_000_000, number_data_point::Value::AsInt(1024))],
+				})),
+				..Default::default()
+			},
+			Metric {
+				name: "http.requests".to_string(),
+				data: Some(Data::Sum(Sum {
+					data_points: vec![number(3_000_000, number_data_point::Value::AsDouble(7.5))],
+				})),
+				..Default::default()
+			},
+		]);
+
+		let batch = decode(&raw_data).unwrap();
+		assert_eq!(batch.multiple_points.len(), 2);
+
+		let gauge = &batch.multiple_points[0];
+		assert_eq!(gauge.name, "system.memory.usage");
+		assert_eq!(gauge.value, 1024.0);
+		assert_eq!(gauge.timestamp, 2);
+		assert_eq!(gauge.labels["service.name"], "api");
+		assert_eq!(gauge.labels["replica"], "2");
+		assert_eq!(gauge.labels["state"], "used");
+
+		let sum = &batch.multiple_points[1];
+		assert_eq!(sum.name, "http.requests");
+		assert_eq!(sum.value, 7.5);
+		assert_eq!(sum.timestamp, 3);
+	}
+
+	#[test]
+	fn test_decode_histogram() {
+		let raw_data = request(vec![Metric {
+			name: "http.duration".to_string(),
+			data: Some(Data::Histogram(Histogram {
+				data_points: vec![HistogramDataPoint {
+					time_unix_nano: 1_000_000,
+					count: 6,
+					sum: 12.5,
+					bucket_counts: vec![1, 2, 3],
+					explicit_bounds: vec![0.5, 1.0],
+					..Default::default()
+				}],
+			})),
+			..Default::default()
+		}]);
+
+		let batch = decode(&raw_data).unwrap();
+		let points: Vec<_> = batch
+			.multiple_points
+			.iter()
+			.map(|point| {
+				(
+					point.name.as_str(),
+					point.labels.get("le").map(|le| le.as_str()),
+					point.value,
+				)
+			})
+			.collect();
+		assert_eq!(
+			points,
+			vec![
+				("http.duration_count", None, 6.0),
+				("http.duration_sum", None, 12.5),
+				("http.duration_bucket", Some("0.5"), 1.0),
+				("http.duration_bucket", Some("1"), 3.0),
+				("http.duration_bucket", Some("+Inf"), 6.0),
+			]
+		);
+		assert!(batch
+			.multiple_points
+			.iter()
+			.all(|point| point.labels["service.name"] == "api"));
+	}
+
+	#[test]
+	fn test_decode_invalid_payload() {
+		assert!(decode(b"definitely not protobuf").is_err());
+	}
+}
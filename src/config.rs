@@ -22,6 +22,9 @@
 
 use std::env;
 
+use crate::{
+	codec::Codec, postgres::ConflictPolicy, supervisor::FailurePolicy, timeliness::TimePolicy,
+};
 use log::info;
 use serde::Deserialize;
 const DEFAULT_CONFIG_ENV_KEY: &str = "APPLICATION_CONFIG_PATH";
@@ -43,6 +46,66 @@ impl ConfigFn {
 	fn fn_default_port() -> String {
 		"8080".into()
 	}
+	fn fn_default_quarantine_path() -> String {
+		"quarantine".into()
+	}
+	fn fn_default_group_id() -> String {
+		"kafka-rust-example".into()
+	}
+	fn fn_default_auto_offset_reset() -> String {
+		"latest".into()
+	}
+	fn fn_default_drain_timeout_secs() -> u64 {
+		30
+	}
+	fn fn_default_subscriber_concurrency() -> usize {
+		4
+	}
+	fn fn_default_subscriber_queue_capacity() -> usize {
+		100
+	}
+	fn fn_default_pause_high_water_mark() -> usize {
+		80
+	}
+	fn fn_default_pause_low_water_mark() -> usize {
+		20
+	}
+	fn fn_default_metrics_report_interval_secs() -> u64 {
+		60
+	}
+	fn fn_default_statistics_interval_ms() -> u64 {
+		60_000
+	}
+	fn fn_default_shutdown_timeout_secs() -> u64 {
+		30
+	}
+	fn fn_default_supervisor_max_backoff_secs() -> u64 {
+		60
+	}
+	fn fn_default_dedup_window_size() -> usize {
+		10_000
+	}
+	fn fn_default_dedup_table_retention_hours() -> u64 {
+		24
+	}
+	fn fn_default_late_data_max_age_secs() -> u64 {
+		7 * 24 * 3600
+	}
+	fn fn_default_future_data_max_skew_secs() -> u64 {
+		300
+	}
+	fn fn_default_postgres_copy_threshold() -> usize {
+		crate::postgres::DEFAULT_COPY_THRESHOLD
+	}
+	fn fn_default_postgres_retry_max_attempts() -> u32 {
+		5
+	}
+	fn fn_default_postgres_retry_initial_backoff_ms() -> u64 {
+		100
+	}
+	fn fn_default_postgres_retry_max_backoff_ms() -> u64 {
+		5000
+	}
 }
 
 #[derive(Deserialize, Debug, Default)]
@@ -63,6 +126,11 @@ pub struct Config {
 	/// Kafka topic on which we want to publish the data.
 	pub kafka_topic: String,
 
+	/// Comma separated topics the subscribers consume, entries starting with
+	/// `^` are regular expressions. Defaults to the kafka topic.
+	#[serde(default)]
+	pub kafka_subscriptions: Vec<String>,
+
 	/// Kafka brokers to connect to.
 	pub kafka_brokers: String,
 
@@ -75,14 +143,179 @@ pub struct Config {
 	/// Kafka ca-cert path for sasl authentication.
 	pub kafka_ca_cert_path: Option<String>,
 
+	/// Codec used to encode published batches, defaults to protobuf.
+	/// Subscribers fall back to it when a message has no content-type header.
+	#[serde(default)]
+	pub kafka_codec: Codec,
+
+	/// Comma separated `key-id:secret` entries to sign batches with.
+	/// Publishers sign with the first key, subscribers only accept messages
+	/// signed with any of the keys. Signing is disabled when empty.
+	#[serde(default)]
+	pub kafka_signing_keys: Vec<String>,
+
+	/// Directory in which subscribers keep messages failing verification.
+	#[serde(default = "ConfigFn::fn_default_quarantine_path")]
+	pub kafka_quarantine_path: String,
+
+	/// Path to a json keyring of AES-256-GCM keys. Publishers encrypt batches
+	/// with its primary key, subscribers decrypt with any of its keys.
+	pub kafka_keyring_path: Option<String>,
+
+	/// Let subscribers with a keyring accept messages which aren't encrypted,
+	/// e.g. while encryption is rolled out to the publishers.
+	#[serde(default)]
+	pub kafka_accept_plaintext: bool,
+
+	/// Path to a json list of stages which transform and filter the batches
+	/// before subscribers write them, see `Pipeline` in src/pipeline.rs.
+	pub pipeline_path: Option<String>,
+
+	/// Number of the last written batch ids a subscriber remembers, to skip
+	/// duplicates of them. 0 disables it.
+	#[serde(default = "ConfigFn::fn_default_dedup_window_size")]
+	pub dedup_window_size: usize,
+
+	/// Record the batch ids in postgres along with the rows, to skip
+	/// duplicates across restarts.
+	#[serde(default)]
+	pub dedup_table: bool,
+
+	/// How long batch ids are kept in postgres.
+	#[serde(default = "ConfigFn::fn_default_dedup_table_retention_hours")]
+	pub dedup_table_retention_hours: u64,
+
+	/// Points older than this are late.
+	#[serde(default = "ConfigFn::fn_default_late_data_max_age_secs")]
+	pub late_data_max_age_secs: u64,
+
+	/// What subscribers do with late points: accept, drop, clamp or route.
+	#[serde(default)]
+	pub late_data_policy: TimePolicy,
+
+	/// Points further ahead of the subscriber's clock than this are from the
+	/// future.
+	#[serde(default = "ConfigFn::fn_default_future_data_max_skew_secs")]
+	pub future_data_max_skew_secs: u64,
+
+	/// What subscribers do with points from the future: accept, drop, clamp
+	/// or route.
+	#[serde(default)]
+	pub future_data_policy: TimePolicy,
+
+	/// Write each batch and its kafka offset in the same postgres transaction,
+	/// and resume partitions from the offsets stored there.
+	#[serde(default)]
+	pub kafka_exactly_once: bool,
+
+	/// Kafka topic which subscribers publish messages to, that can't be
+	/// decoded or written to the database.
+	pub kafka_dead_letter_topic: Option<String>,
+
+	/// Consumer group of the subscribers. Keep it stable across restarts so
+	/// that subscribers resume from the committed offsets.
+	#[serde(default = "ConfigFn::fn_default_group_id")]
+	pub kafka_group_id: String,
+
+	/// Where a consumer group without committed offsets starts consuming:
+	/// earliest, latest or error.
+	#[serde(default = "ConfigFn::fn_default_auto_offset_reset")]
+	pub kafka_auto_offset_reset: String,
+
+	/// Static group membership id of this subscriber, e.g. the pod name of a
+	/// statefulset. Restarts within the session timeout then don't trigger a
+	/// rebalance.
+	pub kafka_group_instance_id: Option<String>,
+
+	/// How long a subscriber waits for the database writes of partitions
+	/// which are revoked, before giving them up.
+	#[serde(default = "ConfigFn::fn_default_drain_timeout_secs")]
+	pub kafka_drain_timeout_secs: u64,
+
+	/// Number of workers writing to the database in parallel. Partitions are
+	/// spread over the workers, each partition is written in order.
+	#[serde(default = "ConfigFn::fn_default_subscriber_concurrency")]
+	pub kafka_subscriber_concurrency: usize,
+
+	/// Number of messages each worker queue holds.
+	#[serde(default = "ConfigFn::fn_default_subscriber_queue_capacity")]
+	pub kafka_subscriber_queue_capacity: usize,
+
+	/// Number of messages waiting in a worker queue at which the subscriber
+	/// pauses its partitions, at most the queue capacity.
+	#[serde(default = "ConfigFn::fn_default_pause_high_water_mark")]
+	pub kafka_pause_high_water_mark: usize,
+
+	/// Number of messages waiting in every worker queue at or below which a
+	/// paused subscriber resumes its partitions.
+	#[serde(default = "ConfigFn::fn_default_pause_low_water_mark")]
+	pub kafka_pause_low_water_mark: usize,
+
+	/// Interval at which the subscriber logs its own metrics.
+	#[serde(default = "ConfigFn::fn_default_metrics_report_interval_secs")]
+	pub metrics_report_interval_secs: u64,
+
+	/// Interval at which the subscriber reports its lag from the kafka
+	/// client statistics, 0 disables them.
+	#[serde(default = "ConfigFn::fn_default_statistics_interval_ms")]
+	pub kafka_statistics_interval_ms: u64,
+
+	/// How long the publisher and subscriber may take to finish their work
+	/// after SIGINT or SIGTERM, before they exit anyway.
+	#[serde(default = "ConfigFn::fn_default_shutdown_timeout_secs")]
+	pub shutdown_timeout_secs: u64,
+
+	/// What happens when a task of the publisher or subscriber panics:
+	/// restart it, or fail and shut the process down.
+	#[serde(default)]
+	pub supervisor_policy: FailurePolicy,
+
+	/// Upper bound of the backoff between restarts of a task.
+	#[serde(default = "ConfigFn::fn_default_supervisor_max_backoff_secs")]
+	pub supervisor_max_backoff_secs: u64,
+
 	/// Postgres database url
 	pub postgres_database_url: String,
 
 	/// Postgres path to cert.
 	pub postgres_cert_path: Option<String>,
+
+	/// Batches with at least this many points are written with a binary
+	/// COPY instead of a single INSERT of arrays, 0 disables COPY.
+	#[serde(default = "ConfigFn::fn_default_postgres_copy_threshold")]
+	pub postgres_copy_threshold: usize,
+
+	/// What happens to points which were written before: fail the batch
+	/// with `error`, keep the existing row with `nothing` or overwrite its
+	/// value with `update`.
+	#[serde(default)]
+	pub postgres_conflict_policy: ConflictPolicy,
+
+	/// Number of attempts to write a batch, when the database fails with
+	/// transient errors like a lost connection.
+	#[serde(default = "ConfigFn::fn_default_postgres_retry_max_attempts")]
+	pub postgres_retry_max_attempts: u32,
+
+	/// Upper bound of the backoff after the first failed attempt, it doubles
+	/// with every attempt.
+	#[serde(default = "ConfigFn::fn_default_postgres_retry_initial_backoff_ms")]
+	pub postgres_retry_initial_backoff_ms: u64,
+
+	/// Upper bound of every backoff between attempts.
+	#[serde(default = "ConfigFn::fn_default_postgres_retry_max_backoff_ms")]
+	pub postgres_retry_max_backoff_ms: u64,
 }
 
 impl Config {
+	/// Topics and patterns the subscribers consume.
+	pub fn subscriptions(&self) -> Vec<String> {
+		if self.kafka_subscriptions.is_empty() {
+			vec![self.kafka_topic.clone()]
+		} else {
+			self.kafka_subscriptions.clone()
+		}
+	}
+
 	// Create a new Config instance by reading from
 	// environment variables
 	pub fn new() -> Config {
@@ -148,6 +381,7 @@ mod tests {
 				kafka_ca_cert_path: None,
 				kafka_username: None,
 				kafka_password: None,
+				..Default::default()
 			},
 			Config {
 				debug: true,
@@ -160,6 +394,7 @@ mod tests {
 				kafka_ca_cert_path: None,
 				kafka_username: None,
 				kafka_password: None,
+				..Default::default()
 			},
 		];
 		assert_eq!(
@@ -178,5 +413,8 @@ mod tests {
 		assert!(config.kafka_brokers == "localhost:9092");
 		assert!(config.kafka_topic == "metrics");
 		assert!(config.debug);
+		assert!(config.kafka_group_id == "kafka-rust-example");
+		assert!(config.kafka_auto_offset_reset == "latest");
+		assert!(config.kafka_group_instance_id.is_none());
 	}
 }
@@ -0,0 +1,126 @@
+// MIT License
+//
+// Copyright (c) 2019 Ankur Srivastava
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use crate::kafka::KafkaMessage;
+use std::collections::{HashSet, VecDeque};
+use uuid::Uuid;
+
+/// Name of the kafka header which carries the id of a batch. Publishers set
+/// a new UUID for every batch, which stays the same when the producer
+/// retries to publish it.
+pub const BATCH_ID_HEADER: &str = "batch-id";
+
+/// Get the batch id of a message, if it carries a valid one.
+pub fn batch_id(kmessage: &KafkaMessage) -> Option<Uuid> {
+	kmessage
+		.header(BATCH_ID_HEADER)
+		.and_then(|id| std::str::from_utf8(id).ok())
+		.and_then(|id| Uuid::parse_str(id).ok())
+}
+
+/// The ids of the batches which were written last.
+///
+/// Once it is full, the id written first is forgotten for every new one, so
+/// it only catches duplicates which arrive shortly after each other, like
+/// producer retries and redeliveries after a rebalance.
+#[derive(Debug, Default)]
+pub struct DedupWindow {
+	capacity: usize,
+	ids: HashSet<Uuid>,
+	order: VecDeque<Uuid>,
+}
+
+impl DedupWindow {
+	/// Create a DedupWindow remembering up to `capacity` batches, none for 0.
+	///
+	/// # Examples
+	/// Basic usage:
+	///
+	/// ```rust norun
+	/// let mut window = DedupWindow::new(10_000);
+	/// window.insert(batch_id);
+	/// assert!(window.contains(&batch_id));
+	/// ```
+	pub fn new(capacity: usize) -> DedupWindow {
+		DedupWindow {
+			capacity,
+			..Default::default()
+		}
+	}
+
+	/// Check whether the batch was written within the window.
+	pub fn contains(&self, id: &Uuid) -> bool {
+		self.ids.contains(id)
+	}
+
+	/// Remember that the batch was written.
+	pub fn insert(&mut self, id: Uuid) {
+		if self.capacity == 0 || !self.ids.insert(id) {
+			return;
+		}
+		self.order.push_back(id);
+		if self.order.len() > self.capacity {
+			if let Some(oldest) = self.order.pop_front() {
+				self.ids.remove(&oldest);
+			}
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_window_forgets_oldest() {
+		let ids: Vec<Uuid> = (0..3).map(|_| Uuid::new_v4()).collect();
+		let mut window = DedupWindow::new(2);
+		window.insert(ids[0]);
+		window.insert(ids[1]);
+		window.insert(ids[1]);
+		assert!(window.contains(&ids[0]));
+
+		window.insert(ids[2]);
+		assert!(!window.contains(&ids[0]));
+		assert!(window.contains(&ids[1]));
+		assert!(window.contains(&ids[2]));
+
+		let mut disabled = DedupWindow::new(0);
+		disabled.insert(ids[0]);
+		assert!(!disabled.contains(&ids[0]));
+	}
+
+	#[test]
+	fn test_batch_id() {
+		let id = Uuid::new_v4();
+		let mut kmessage = KafkaMessage::default();
+		assert_eq!(batch_id(&kmessage), None);
+		kmessage
+			.headers
+			.insert(BATCH_ID_HEADER.to_string(), id.to_string().into_bytes());
+		assert_eq!(batch_id(&kmessage), Some(id));
+		kmessage
+			.headers
+			.insert(BATCH_ID_HEADER.to_string(), b"not-a-uuid".to_vec());
+		assert_eq!(batch_id(&kmessage), None);
+	}
+}
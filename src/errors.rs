@@ -20,9 +20,10 @@
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
 // SOFTWARE.
 
+use crate::codec::Codec;
 use deadpool_postgres::BuildError;
 use deadpool_postgres::PoolError;
-use std::io;
+use std::{error::Error as _, io};
 use thiserror::Error;
 
 /// Default AppError which provides translation between one error type to
@@ -43,4 +44,85 @@ pub enum AppError {
 
 	#[error(transparent)]
 	Tls(#[from] native_tls::Error),
+
+	#[error("Failed to decode a protobuf payload")]
+	ProtoDecode(#[from] prost::DecodeError),
+
+	#[error("Failed to encode a protobuf payload")]
+	ProtoEncode(#[from] prost::EncodeError),
+
+	#[error("Failed to encode or decode an avro payload")]
+	Avro(#[from] apache_avro::Error),
+
+	#[error("Failed to parse line protocol: {0}")]
+	LineProtocol(String),
+
+	#[error("Encoding with the {0:?} codec is not supported")]
+	UnsupportedEncoding(Codec),
+
+	#[error("Failed to decode a columnar batch: {0}")]
+	Columnar(String),
+
+	#[error("Failed to verify the message signature: {0}")]
+	Signature(String),
+
+	#[error("Failed to encrypt or decrypt the message: {0}")]
+	Encryption(String),
+
+	#[error(transparent)]
+	Json(#[from] serde_json::Error),
+
+	#[error("Kafka request failed")]
+	Kafka(#[from] rdkafka::error::KafkaError),
+
+	#[error("Invalid position: {0}")]
+	Position(String),
+
+	#[error("Invalid kafka subscription: {0}")]
+	Subscription(String),
+
+	#[error("Invalid water marks: {0}")]
+	WaterMarks(String),
+
+	#[error("Invalid pipeline: {0}")]
+	Pipeline(String),
+
+	#[error("Timestamp {0} is out of range")]
+	Timestamp(i64),
+
+	#[error("Task {0} failed")]
+	TaskFailed(String),
+}
+
+impl AppError {
+	/// Check whether the error may go away when the operation is retried,
+	/// like a lost connection or an exhausted pool. Errors like constraint
+	/// violations fail every time.
+	pub fn is_transient(&self) -> bool {
+		match self {
+			AppError::PoolConn(PoolError::Timeout(_)) => true,
+			AppError::PoolConn(PoolError::Backend(e)) | AppError::TokioConn(e) => {
+				is_transient_postgres(e)
+			}
+			AppError::Io(_) => true,
+			_ => false,
+		}
+	}
+}
+
+fn is_transient_postgres(e: &tokio_postgres::Error) -> bool {
+	match e.code() {
+		Some(state) => {
+			let code = state.code();
+			// Connection exceptions, insufficient resources, operator
+			// intervention like a restart, serialization failures and deadlocks.
+			code.starts_with("08")
+				|| code.starts_with("53")
+				|| code.starts_with("57P")
+				|| code == "40001"
+				|| code == "40P01"
+		}
+		// Errors which didn't come from the server.
+		None => e.is_closed() || matches!(e.source(), Some(source) if source.is::<io::Error>()),
+	}
 }
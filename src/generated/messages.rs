@@ -11,4 +11,35 @@ pub struct Message {
 	pub name: ::prost::alloc::string::String,
 	#[prost(float, tag = "3")]
 	pub value: f32,
+	#[prost(map = "string, string", tag = "4")]
+	pub labels:
+		::std::collections::HashMap<::prost::alloc::string::String, ::prost::alloc::string::String>,
+}
+/// Column oriented alternative to BatchMessage for high volume publishers.
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct ColumnarBatchMessage {
+	/// Dictionary of the distinct metric names in this batch.
+	#[prost(string, repeated, tag = "1")]
+	pub names: ::prost::alloc::vec::Vec<::prost::alloc::string::String>,
+	/// Index into names for every point.
+	#[prost(uint32, repeated, tag = "2")]
+	pub name_ids: ::prost::alloc::vec::Vec<u32>,
+	/// Dictionary of the distinct label sets in this batch.
+	#[prost(message, repeated, tag = "3")]
+	pub label_sets: ::prost::alloc::vec::Vec<LabelSet>,
+	/// Index into label_sets for every point.
+	#[prost(uint32, repeated, tag = "4")]
+	pub label_set_ids: ::prost::alloc::vec::Vec<u32>,
+	/// First timestamp, first delta and then the delta-of-delta for every point.
+	#[prost(sint64, repeated, tag = "5")]
+	pub timestamps: ::prost::alloc::vec::Vec<i64>,
+	/// Gorilla XOR compressed values of every point.
+	#[prost(bytes = "vec", tag = "6")]
+	pub values: ::prost::alloc::vec::Vec<u8>,
+}
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct LabelSet {
+	#[prost(map = "string, string", tag = "1")]
+	pub labels:
+		::std::collections::HashMap<::prost::alloc::string::String, ::prost::alloc::string::String>,
 }
@@ -1,2 +1,3 @@
 mod messages;
-pub use messages::{BatchMessage, Message};
+pub mod otlp;
+pub use messages::{BatchMessage, ColumnarBatchMessage, LabelSet, Message};
@@ -0,0 +1,120 @@
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct ExportMetricsServiceRequest {
+	#[prost(message, repeated, tag = "1")]
+	pub resource_metrics: ::prost::alloc::vec::Vec<ResourceMetrics>,
+}
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct ResourceMetrics {
+	#[prost(message, optional, tag = "1")]
+	pub resource: ::core::option::Option<Resource>,
+	#[prost(message, repeated, tag = "2")]
+	pub scope_metrics: ::prost::alloc::vec::Vec<ScopeMetrics>,
+}
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Resource {
+	#[prost(message, repeated, tag = "1")]
+	pub attributes: ::prost::alloc::vec::Vec<KeyValue>,
+}
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct ScopeMetrics {
+	#[prost(message, repeated, tag = "2")]
+	pub metrics: ::prost::alloc::vec::Vec<Metric>,
+}
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Metric {
+	#[prost(string, tag = "1")]
+	pub name: ::prost::alloc::string::String,
+	#[prost(string, tag = "2")]
+	pub description: ::prost::alloc::string::String,
+	#[prost(string, tag = "3")]
+	pub unit: ::prost::alloc::string::String,
+	#[prost(oneof = "metric::Data", tags = "5, 7, 9")]
+	pub data: ::core::option::Option<metric::Data>,
+}
+/// Nested message and enum types in `Metric`.
+pub mod metric {
+	#[derive(Clone, PartialEq, ::prost::Oneof)]
+	pub enum Data {
+		#[prost(message, tag = "5")]
+		Gauge(super::Gauge),
+		#[prost(message, tag = "7")]
+		Sum(super::Sum),
+		#[prost(message, tag = "9")]
+		Histogram(super::Histogram),
+	}
+}
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Gauge {
+	#[prost(message, repeated, tag = "1")]
+	pub data_points: ::prost::alloc::vec::Vec<NumberDataPoint>,
+}
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Sum {
+	#[prost(message, repeated, tag = "1")]
+	pub data_points: ::prost::alloc::vec::Vec<NumberDataPoint>,
+}
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Histogram {
+	#[prost(message, repeated, tag = "1")]
+	pub data_points: ::prost::alloc::vec::Vec<HistogramDataPoint>,
+}
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct NumberDataPoint {
+	#[prost(message, repeated, tag = "7")]
+	pub attributes: ::prost::alloc::vec::Vec<KeyValue>,
+	#[prost(fixed64, tag = "3")]
+	pub time_unix_nano: u64,
+	#[prost(oneof = "number_data_point::Value", tags = "4, 6")]
+	pub value: ::core::option::Option<number_data_point::Value>,
+}
+/// Nested message and enum types in `NumberDataPoint`.
+pub mod number_data_point {
+	#[derive(Clone, PartialEq, ::prost::Oneof)]
+	pub enum Value {
+		#[prost(double, tag = "4")]
+		AsDouble(f64),
+		#[prost(sfixed64, tag = "6")]
+		AsInt(i64),
+	}
+}
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct HistogramDataPoint {
+	#[prost(message, repeated, tag = "9")]
+	pub attributes: ::prost::alloc::vec::Vec<KeyValue>,
+	#[prost(fixed64, tag = "3")]
+	pub time_unix_nano: u64,
+	#[prost(fixed64, tag = "4")]
+	pub count: u64,
+	#[prost(double, tag = "5")]
+	pub sum: f64,
+	#[prost(fixed64, repeated, tag = "6")]
+	pub bucket_counts: ::prost::alloc::vec::Vec<u64>,
+	#[prost(double, repeated, tag = "7")]
+	pub explicit_bounds: ::prost::alloc::vec::Vec<f64>,
+}
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct KeyValue {
+	#[prost(string, tag = "1")]
+	pub key: ::prost::alloc::string::String,
+	#[prost(message, optional, tag = "2")]
+	pub value: ::core::option::Option<AnyValue>,
+}
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct AnyValue {
+	#[prost(oneof = "any_value::Value", tags = "1, 2, 3, 4")]
+	pub value: ::core::option::Option<any_value::Value>,
+}
+/// Nested message and enum types in `AnyValue`.
+pub mod any_value {
+	#[derive(Clone, PartialEq, ::prost::Oneof)]
+	pub enum Value {
+		#[prost(string, tag = "1")]
+		StringValue(::prost::alloc::string::String),
+		#[prost(bool, tag = "2")]
+		BoolValue(bool),
+		#[prost(int64, tag = "3")]
+		IntValue(i64),
+		#[prost(double, tag = "4")]
+		DoubleValue(f64),
+	}
+}
@@ -20,20 +20,191 @@
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
 // SOFTWARE.
 
+use crate::{
+	errors::AppError,
+	kafka::{
+		dead_letter::{replay_headers, to_owned_headers},
+		KafkaProducer, Position, Quarantine, SigningKeys, SubscriberContext,
+		DLQ_SOURCE_TOPIC_HEADER,
+	},
+	metrics::Registry,
+	postgres::DbClient,
+	shutdown::Shutdown,
+};
 use futures::StreamExt;
-use log::{debug, error, warn};
+use log::{debug, error, info, warn};
 
 use prost::bytes::BytesMut;
 
 use rdkafka::{
 	config::{ClientConfig, RDKafkaLogLevel},
 	consumer::{stream_consumer::StreamConsumer, CommitMode, Consumer},
-	message::Message,
+	error::KafkaError,
+	message::{BorrowedMessage, Headers, Message},
+	topic_partition_list::{Offset, TopicPartitionList},
+};
+use std::{
+	collections::{hash_map::DefaultHasher, HashMap, HashSet},
+	future,
+	hash::{Hash, Hasher},
+	sync::Arc,
+};
+use tokio::{
+	self,
+	sync::mpsc,
+	task,
+	time::{self, Duration},
 };
-use tokio::{self, sync::mpsc};
+
+/// Raw payload of a kafka message along with its headers and position.
+#[derive(Debug, Default)]
+pub struct KafkaMessage {
+	pub payload: BytesMut,
+	pub headers: HashMap<String, Vec<u8>>,
+	/// Topic the message was consumed from, one of many when subscribed to
+	/// several topics or a pattern.
+	pub topic: String,
+	pub partition: i32,
+	pub offset: i64,
+}
+
+impl KafkaMessage {
+	/// Copy the payload, headers and position out of a consumed message.
+	/// Returns None for messages without a payload.
+	fn from_borrowed(m: &BorrowedMessage) -> Option<KafkaMessage> {
+		let headers = m
+			.headers()
+			.map(|headers| {
+				(0..headers.count())
+					.filter_map(|idx| headers.get(idx))
+					.map(|(name, value)| (name.to_string(), value.to_vec()))
+					.collect()
+			})
+			.unwrap_or_default();
+		Some(KafkaMessage {
+			payload: BytesMut::from(m.payload()?),
+			headers,
+			topic: m.topic().to_string(),
+			partition: m.partition(),
+			offset: m.offset(),
+		})
+	}
+
+	/// Get the value of a header by its name.
+	pub fn header(&self, name: &str) -> Option<&[u8]> {
+		self.headers.get(name).map(|value| &value[..])
+	}
+
+	/// Index of the queue out of `queues` which this message is handed to.
+	///
+	/// The partitions of a topic are spread round-robin over the queues, and
+	/// every message of a partition ends up on the same queue.
+	pub fn queue(&self, queues: usize) -> usize {
+		let mut hasher = DefaultHasher::new();
+		self.topic.hash(&mut hasher);
+		(hasher.finish() as usize).wrapping_add(self.partition as usize) % queues
+	}
+
+	/// Acknowledge that this message was handled and its offset can be committed.
+	pub fn ack(&self) -> Ack {
+		Ack {
+			topic: self.topic.clone(),
+			partition: self.partition,
+			offset: self.offset,
+		}
+	}
+}
+
+/// Position of a message which was handled by the sink.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Ack {
+	pub topic: String,
+	pub partition: i32,
+	pub offset: i64,
+}
+
+/// Handle for the sink to acknowledge the messages it is done with.
+#[derive(Clone)]
+pub struct Acknowledger {
+	context: Arc<SubscriberContext>,
+}
+
+impl Acknowledger {
+	pub(crate) fn new(context: Arc<SubscriberContext>) -> Acknowledger {
+		Acknowledger { context }
+	}
+
+	/// Mark a message as handled, so that its offset can be committed.
+	pub fn ack(&self, ack: &Ack) {
+		self.context.ack(ack);
+	}
+}
+
+/// Fill levels of the sink queues at which the consumer pauses and resumes
+/// its partitions.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct WaterMarks {
+	pub high: usize,
+	pub low: usize,
+}
+
+impl WaterMarks {
+	/// Create WaterMarks for queues holding up to `capacity` messages.
+	///
+	/// The low water mark has to be below the high one, which can't exceed
+	/// the capacity, as a full queue blocks the consumer before it can pause.
+	///
+	/// # Examples
+	/// Basic usage:
+	///
+	/// ```rust norun
+	/// let water_marks = WaterMarks::new(80, 20, 100)?;
+	/// ```
+	pub fn new(high: usize, low: usize, capacity: usize) -> Result<WaterMarks, AppError> {
+		if low >= high {
+			return Err(AppError::WaterMarks(format!(
+				"the low water mark {} has to be below the high water mark {}",
+				low, high
+			)));
+		}
+		if high > capacity {
+			return Err(AppError::WaterMarks(format!(
+				"the high water mark {} exceeds the queue capacity {}",
+				high, capacity
+			)));
+		}
+		Ok(WaterMarks { high, low })
+	}
+
+	/// Whether the consumer should be paused, given whether it is paused now
+	/// and the fill level of the fullest queue.
+	fn should_pause(&self, paused: bool, depth: usize) -> bool {
+		if paused {
+			depth > self.low
+		} else {
+			depth >= self.high
+		}
+	}
+}
+
+/// Per partition progress of positioning the consumer.
+#[derive(Debug, Default)]
+struct Positions {
+	/// Partitions which were positioned since the last rebalance.
+	positioned: HashSet<(String, i32)>,
+	/// Offsets at which partitions end.
+	ends: HashMap<(String, i32), i64>,
+	/// Partitions which reached their end.
+	finished: HashSet<(String, i32)>,
+}
 
 pub struct KafkaConsumer {
-	kafka_consumer: StreamConsumer,
+	kafka_consumer: Arc<StreamConsumer<SubscriberContext>>,
+	verification: Option<(SigningKeys, Quarantine)>,
+	offset_store: Option<DbClient>,
+	water_marks: Option<WaterMarks>,
+	end: Option<Position>,
+	shutdown: Option<Shutdown>,
 }
 
 impl KafkaConsumer {
@@ -47,66 +218,655 @@ impl KafkaConsumer {
 	/// ```
 	pub fn new(kafka_brokers: &str, group_id: &str, topics: &[&str]) -> KafkaConsumer {
 		// Create the `Futureconsumer` to produce asynchronously.
-		let consumer: StreamConsumer = ClientConfig::new()
+		let consumer: StreamConsumer<SubscriberContext> = ClientConfig::new()
 			.set("group.id", group_id)
 			.set("bootstrap.servers", kafka_brokers)
 			.set("enable.partition.eof", "false")
 			.set("session.timeout.ms", "6000")
-			.set("enable.auto.commit", "true")
+			.set("enable.auto.commit", "false")
 			.set_log_level(RDKafkaLogLevel::Debug)
-			.create()
+			.create_with_context(SubscriberContext::default())
 			.expect("Consumer creation failed");
 
+		KafkaConsumer::new_with_consumer(consumer, topics)
+	}
+
+	pub fn new_with_consumer(
+		consumer: StreamConsumer<SubscriberContext>,
+		topics: &[&str],
+	) -> KafkaConsumer {
 		consumer
 			.subscribe(topics)
 			.expect("Failed to subscribe to specified topics");
 
+		let consumer = Arc::new(consumer);
+		consumer.context().set_consumer(Arc::downgrade(&consumer));
 		KafkaConsumer {
 			kafka_consumer: consumer,
+			verification: None,
+			offset_store: None,
+			water_marks: None,
+			end: None,
+			shutdown: None,
 		}
 	}
 
-	pub fn new_with_consumer(consumer: StreamConsumer, topics: &[&str]) -> KafkaConsumer {
-		consumer
-			.subscribe(topics)
-			.expect("Failed to subscribe to specified topics");
+	/// Get a handle for the sink to acknowledge messages with.
+	pub fn acknowledger(&self) -> Acknowledger {
+		Acknowledger::new(self.kafka_consumer.context().clone())
+	}
 
-		KafkaConsumer {
-			kafka_consumer: consumer,
+	/// Topic partitions currently assigned to this consumer.
+	pub fn assignment(&self) -> Vec<(String, i32)> {
+		self.kafka_consumer.context().assignment()
+	}
+
+	/// Metrics of the consumer and of the sink it feeds.
+	pub fn metrics(&self) -> Arc<Registry> {
+		self.kafka_consumer.context().metrics().clone()
+	}
+
+	/// Pause all assigned partitions while a sink queue is filled up to the
+	/// high-water mark, and resume them once every queue is at or below the
+	/// low-water mark.
+	///
+	/// Unlike blocking on a full queue, the consumer keeps polling while it
+	/// is paused. So it stays in the group even if the sink is slow.
+	pub fn with_backpressure(mut self, water_marks: WaterMarks) -> KafkaConsumer {
+		self.water_marks = Some(water_marks);
+		self
+	}
+
+	/// Start consuming every partition at the given position, instead of the
+	/// committed offset of the consumer group.
+	///
+	/// The position is applied when a partition is assigned for the first
+	/// time, so it also takes effect for partitions without new messages,
+	/// e.g. when replaying a group which consumed everything before.
+	pub fn with_start(self, start: Position) -> KafkaConsumer {
+		self.kafka_consumer.context().set_start(start);
+		self
+	}
+
+	/// Stop consuming a partition at the given position, not including it,
+	/// or at its end if that comes first. `consume` returns once every
+	/// partition stopped and the sink wrote the messages before.
+	///
+	/// Reaching the end of a partition is only noticed with
+	/// `enable.partition.eof` set.
+	pub fn with_end(mut self, end: Position) -> KafkaConsumer {
+		self.end = Some(end);
+		self
+	}
+
+	/// Stop consuming once the shutdown is requested. `consume` then waits
+	/// for the sink to write what was consumed so far, and commits it.
+	pub fn with_shutdown(mut self, shutdown: Shutdown) -> KafkaConsumer {
+		self.shutdown = Some(shutdown);
+		self
+	}
+
+	/// Only accept messages signed with one of the given keys.
+	///
+	/// Unsigned messages and messages whose signature doesn't match are not
+	/// passed on, but stored in the quarantine instead.
+	pub fn with_signature_verification(
+		mut self,
+		keys: SigningKeys,
+		quarantine: Quarantine,
+	) -> KafkaConsumer {
+		self.verification = Some((keys, quarantine));
+		self
+	}
+
+	/// Start every assigned partition from the offset stored in postgres.
+	///
+	/// Used together with `DbClient::insert_with_offset`, which stores the
+	/// offsets in the same transaction as the rows.
+	pub fn with_offset_store(mut self, dbclient: DbClient) -> KafkaConsumer {
+		self.offset_store = Some(dbclient);
+		self
+	}
+
+	/// Move a partition to the offset stored in postgres, if any, the first
+	/// time a message of it is received after a rebalance.
+	///
+	/// Returns true if the consumer moved, the message should then be dropped
+	/// as the partition is re-fetched from there.
+	async fn position(&self, positions: &mut Positions, kmessage: &KafkaMessage) -> bool {
+		let key = (kmessage.topic.clone(), kmessage.partition);
+		let dbclient = match &self.offset_store {
+			Some(dbclient) => dbclient,
+			None => return false,
+		};
+		if !positions.positioned.insert(key) {
+			return false;
+		}
+		match dbclient
+			.stored_offset(&kmessage.topic, kmessage.partition)
+			.await
+		{
+			Ok(Some(stored)) if stored != kmessage.offset => {
+				self.seek(kmessage, Offset::Offset(stored))
+			}
+			Ok(_) => false,
+			Err(e) => {
+				// Redeliveries are still skipped when writing, so carry on.
+				error!(
+					"Failed to read the stored offset of {}/{}: {:?}",
+					kmessage.topic, kmessage.partition, e
+				);
+				false
+			}
 		}
 	}
 
-	/// Consume the incoming topic and publishes the raw-payload to an internal
-	/// mpsc channel to be consumed by another async-task which then writes the
-	/// data to postgres.
-	pub async fn consume(&self, sender_tx: mpsc::Sender<BytesMut>) {
+	/// Seek the partition of a message to the given offset.
+	fn seek(&self, kmessage: &KafkaMessage, offset: Offset) -> bool {
+		info!(
+			"Seeking {}/{} from offset {} to {:?}",
+			kmessage.topic, kmessage.partition, kmessage.offset, offset
+		);
+		match self.kafka_consumer.seek(
+			&kmessage.topic,
+			kmessage.partition,
+			offset,
+			Duration::from_secs(10),
+		) {
+			Ok(()) => true,
+			Err(e) => {
+				error!(
+					"Failed to seek {}/{} to {:?}: {:?}",
+					kmessage.topic, kmessage.partition, offset, e
+				);
+				false
+			}
+		}
+	}
+
+	/// Get the offset at which consuming a partition stops.
+	fn resolve_end(&self, end: Position, topic: &str, partition: i32) -> Result<i64, AppError> {
+		let (low, high) =
+			self.kafka_consumer
+				.fetch_watermarks(topic, partition, Duration::from_secs(10))?;
+		Ok(match end {
+			Position::Beginning => low,
+			Position::End => high,
+			Position::Offset(offset) => offset,
+			Position::Timestamp(timestamp) => {
+				match offset_for_time(self.kafka_consumer.as_ref(), topic, partition, timestamp)? {
+					Offset::Offset(offset) => offset,
+					_ => high,
+				}
+			}
+		})
+	}
+
+	/// Get the offset at which consuming a partition stops, resolved once per
+	/// partition. None without an end position or if it can't be resolved.
+	fn end_offset(&self, positions: &mut Positions, topic: &str, partition: i32) -> Option<i64> {
+		let end = self.end?;
+		let key = (topic.to_string(), partition);
+		if let Some(end_offset) = positions.ends.get(&key) {
+			return Some(*end_offset);
+		}
+		match self.resolve_end(end, topic, partition) {
+			Ok(end_offset) => Some(*positions.ends.entry(key).or_insert(end_offset)),
+			Err(e) => {
+				error!(
+					"Failed to resolve {:?} for {}/{}: {:?}",
+					end, topic, partition, e
+				);
+				None
+			}
+		}
+	}
+
+	/// Check whether a message is past the end position of its partition.
+	fn is_past_end(&self, positions: &mut Positions, kmessage: &KafkaMessage) -> bool {
+		match self.end_offset(positions, &kmessage.topic, kmessage.partition) {
+			Some(end_offset) => kmessage.offset >= end_offset,
+			None => false,
+		}
+	}
+
+	/// Check whether the consumed position of a partition reached its end
+	/// position. A partition nothing was consumed from yet is at its
+	/// beginning.
+	fn has_reached_end(&self, positions: &mut Positions, topic: &str, partition: i32) -> bool {
+		let end_offset = match self.end_offset(positions, topic, partition) {
+			Some(end_offset) => end_offset,
+			None => return false,
+		};
+		let consumed = self.kafka_consumer.position().ok().and_then(|tpl| {
+			match tpl.find_partition(topic, partition)?.offset() {
+				Offset::Offset(offset) => Some(offset),
+				_ => None,
+			}
+		});
+		let consumed = match consumed {
+			Some(offset) => offset,
+			None => match self.kafka_consumer.fetch_watermarks(
+				topic,
+				partition,
+				Duration::from_secs(10),
+			) {
+				Ok((low, _)) => low,
+				Err(e) => {
+					error!(
+						"Failed to get the watermarks of {}/{}: {:?}",
+						topic, partition, e
+					);
+					return false;
+				}
+			},
+		};
+		consumed >= end_offset
+	}
+
+	/// Stop consuming a partition which reached its end position.
+	fn finish(&self, positions: &mut Positions, topic: &str, partition: i32) {
+		// Paused again on later messages, as resuming after backpressure
+		// resumes every assigned partition.
+		if positions.finished.insert((topic.to_string(), partition)) {
+			info!("Reached the end of {}/{}", topic, partition);
+		}
+		let mut tpl = TopicPartitionList::new();
+		tpl.add_partition(topic, partition);
+		if let Err(e) = self.kafka_consumer.pause(&tpl) {
+			error!("Failed to pause {}/{}: {:?}", topic, partition, e);
+		}
+	}
+
+	/// Check whether every assigned partition reached its end position.
+	fn is_finished(&self, positions: &Positions) -> bool {
+		let assignment = self.assignment();
+		self.end.is_some()
+			&& !assignment.is_empty()
+			&& assignment
+				.iter()
+				.all(|key| positions.finished.contains(key))
+	}
+
+	/// Pause or resume the assigned partitions depending on the fill level of
+	/// the queues. `paused` holds the rebalance count at which the consumer
+	/// was paused, partitions assigned since then are paused as well.
+	fn apply_backpressure(
+		&self,
+		queues: &[mpsc::Sender<KafkaMessage>],
+		paused: &mut Option<usize>,
+	) {
+		let water_marks = match self.water_marks {
+			Some(water_marks) => water_marks,
+			None => return,
+		};
+		let context = self.kafka_consumer.context();
+		let depth = queues
+			.iter()
+			.map(|queue| queue.max_capacity() - queue.capacity())
+			.max()
+			.unwrap_or(0);
+		context
+			.metrics()
+			.set("kafka-subscriber-queue-depth", &[], depth as f32);
+
+		let pause = water_marks.should_pause(paused.is_some(), depth);
+		let rebalanced = *paused != Some(context.rebalances());
+		if pause == paused.is_some() && !(pause && rebalanced) {
+			return;
+		}
+		let assignment = match self.kafka_consumer.assignment() {
+			Ok(assignment) => assignment,
+			Err(e) => {
+				error!("Failed to get the assigned partitions: {:?}", e);
+				return;
+			}
+		};
+		let result = if pause {
+			self.kafka_consumer.pause(&assignment)
+		} else {
+			self.kafka_consumer.resume(&assignment)
+		};
+		if let Err(e) = result {
+			error!("Failed to pause or resume the assigned partitions: {:?}", e);
+			return;
+		}
+
+		if pause {
+			info!("Sink queue holds {} messages, pausing consumption", depth);
+			*paused = Some(context.rebalances());
+		} else {
+			info!("Sink queue holds {} messages, resuming consumption", depth);
+			*paused = None;
+		}
+		context
+			.metrics()
+			.set("kafka-consumer-paused", &[], if pause { 1.0 } else { 0.0 });
+	}
+
+	/// Check the signature of a message, quarantining it if verification fails.
+	fn is_verified(&self, kmessage: &KafkaMessage) -> bool {
+		let (keys, quarantine) = match &self.verification {
+			Some(verification) => verification,
+			None => return true,
+		};
+		match keys.verify(kmessage) {
+			Ok(()) => true,
+			Err(e) => {
+				warn!(
+					"Quarantining message on offset {:?}: {}",
+					kmessage.offset, e
+				);
+				if let Err(e) = quarantine.store(kmessage, &e.to_string()) {
+					error!("Failed to quarantine the message: {:?}", e);
+				}
+				false
+			}
+		}
+	}
+
+	/// Consume the incoming topic and publishes the raw-payload to one of the
+	/// internal mpsc channels, to be consumed by other async-tasks which then
+	/// write the data to postgres. The messages of a partition are always
+	/// published to the same channel.
+	///
+	/// Offsets are only committed once a task acknowledged the message, and
+	/// every message before it, with the `acknowledger`.
+	pub async fn consume(&self, queues: &[mpsc::Sender<KafkaMessage>]) {
 		debug!("initiating data consumption from kafka-topic");
 
+		let context = self.kafka_consumer.context();
+		let mut positions = Positions::default();
+		let mut rebalances = context.rebalances();
+		let mut paused = None;
+		let mut backpressure_interval = time::interval(Duration::from_millis(250));
+		let mut shutdown = self.shutdown.clone();
 		let mut message_stream = self.kafka_consumer.stream();
-		while let Some(message) = message_stream.next().await {
-			match message {
-				Err(e) => warn!("Kafka error: {}", e),
-				Ok(m) => {
-					if let Some(raw_data) = m.payload() {
+		loop {
+			tokio::select! {
+				message = message_stream.next() => match message {
+					None => break,
+					Some(Err(KafkaError::PartitionEOF(partition))) => {
+						if self.end.is_some() {
+							// The error doesn't name the topic, so check the
+							// partition of every assigned topic against its
+							// own end.
+							for (topic, assigned) in self.assignment() {
+								if assigned == partition
+									&& self.has_reached_end(&mut positions, &topic, partition)
+								{
+									self.finish(&mut positions, &topic, partition);
+								}
+							}
+							if self.is_finished(&positions) {
+								break;
+							}
+						}
+					}
+					Some(Err(e)) => warn!("Kafka error: {}", e),
+					Some(Ok(m)) => {
+						let kmessage = match KafkaMessage::from_borrowed(&m) {
+							Some(kmessage) => kmessage,
+							None => {
+								warn!("Failed to read raw data from kafka topic");
+								context.offsets().skip(m.topic(), m.partition(), m.offset());
+								continue;
+							}
+						};
 						debug!(
 							"Received message on Kafka {:?} on offset {:?}",
-							&raw_data,
-							m.offset()
+							&kmessage.payload, kmessage.offset
 						);
-						let payload = BytesMut::from(raw_data);
-						if let Err(e) = &sender_tx.send(payload).await {
+
+						// Partitions may have moved, so position them again.
+						if rebalances != context.rebalances() {
+							rebalances = context.rebalances();
+							positions.positioned.clear();
+						}
+						if self.position(&mut positions, &kmessage).await {
+							continue;
+						}
+						if self.is_past_end(&mut positions, &kmessage) {
+							self.finish(&mut positions, &kmessage.topic, kmessage.partition);
+							if self.is_finished(&positions) {
+								break;
+							}
+							continue;
+						}
+
+						if !self.is_verified(&kmessage) {
+							context
+								.offsets()
+								.skip(&kmessage.topic, kmessage.partition, kmessage.offset);
+							continue;
+						}
+						context
+							.offsets()
+							.dispatch(&kmessage.topic, kmessage.partition, kmessage.offset);
+						let queue = &queues[kmessage.queue(queues.len())];
+						if let Err(e) = queue.send(kmessage).await {
 							error!("receiver dropped: {:?}", e);
 						}
-					} else {
-						warn!("Failed to read raw data from kafka topic")
+						self.apply_backpressure(queues, &mut paused);
 					}
+				},
+				_ = backpressure_interval.tick() => {
+					self.apply_backpressure(queues, &mut paused);
+				}
+				_ = context.wait_for_ack() => {
+					let committable = context.offsets().take_committable();
+					context.commit(committable, CommitMode::Async);
+				}
+				_ = shutdown_requested(&mut shutdown) => {
+					info!("Stopping to consume from kafka");
+					break;
+				}
+			}
+		}
+		// Wait for the sink to write what was consumed so far, and commit it.
+		let context = context.clone();
+		let assignment = self.assignment();
+		let _ = task::spawn_blocking(move || context.drain(&assignment)).await;
+		debug!("Returned from consumer");
+	}
 
-					if let Err(e) = self.kafka_consumer.commit_message(&m, CommitMode::Async) {
-						error!("Failed to commit offset to kafka: {:?}", e);
-					}
+	/// Publish the consumed messages again to the topic named in their
+	/// dlq-source-topic header, or to `topic` without one, until no message
+	/// arrived for `idle_timeout`. Used to replay a dead-letter topic.
+	///
+	/// Each message is committed once it was published, so an interrupted
+	/// replay continues where it stopped. Returns the number of replayed
+	/// messages.
+	pub async fn replay(
+		&self,
+		producer: &KafkaProducer,
+		topic: &str,
+		idle_timeout: Duration,
+	) -> Result<usize, AppError> {
+		let mut replayed = 0;
+		let mut shutdown = self.shutdown.clone();
+		let mut message_stream = self.kafka_consumer.stream();
+		loop {
+			let next = tokio::select! {
+				next = time::timeout(idle_timeout, message_stream.next()) => next,
+				_ = shutdown_requested(&mut shutdown) => break,
+			};
+			let m = match next {
+				Err(_) | Ok(None) => break,
+				Ok(Some(Err(KafkaError::PartitionEOF(_)))) => continue,
+				Ok(Some(Err(e))) => {
+					warn!("Kafka error: {}", e);
+					continue;
 				}
+				Ok(Some(Ok(m))) => m,
 			};
+			if let Some(kmessage) = KafkaMessage::from_borrowed(&m) {
+				let source_topic = kmessage
+					.header(DLQ_SOURCE_TOPIC_HEADER)
+					.and_then(|source| std::str::from_utf8(source).ok())
+					.unwrap_or(topic)
+					.to_string();
+				let headers = to_owned_headers(replay_headers(&kmessage.headers));
+				producer
+					.produce(kmessage.payload, &source_topic, headers)
+					.await?;
+				replayed += 1;
+			}
+			self.kafka_consumer.commit_message(&m, CommitMode::Sync)?;
 		}
-		debug!("Returned from consumer");
+		Ok(replayed)
+	}
+}
+
+/// Get the offset to start consuming a partition at for a start position.
+pub(crate) fn start_offset<K: Consumer<SubscriberContext>>(
+	consumer: &K,
+	start: Position,
+	topic: &str,
+	partition: i32,
+) -> Result<Offset, AppError> {
+	Ok(match start {
+		Position::Beginning => Offset::Beginning,
+		Position::End => Offset::End,
+		Position::Offset(offset) => Offset::Offset(offset),
+		Position::Timestamp(timestamp) => offset_for_time(consumer, topic, partition, timestamp)?,
+	})
+}
+
+/// Get the first offset of a partition whose timestamp is at or after the
+/// given one, or Offset::End if there is none.
+fn offset_for_time<K: Consumer<SubscriberContext>>(
+	consumer: &K,
+	topic: &str,
+	partition: i32,
+	timestamp: i64,
+) -> Result<Offset, AppError> {
+	let mut tpl = TopicPartitionList::new();
+	tpl.add_partition_offset(topic, partition, Offset::Offset(timestamp))?;
+	let offsets = consumer.offsets_for_times(tpl, Duration::from_secs(10))?;
+	Ok(offsets
+		.find_partition(topic, partition)
+		.map(|elem| elem.offset())
+		.unwrap_or(Offset::End))
+}
+
+/// Wait until the shutdown is requested, forever without a shutdown.
+async fn shutdown_requested(shutdown: &mut Option<Shutdown>) {
+	match shutdown {
+		Some(shutdown) => shutdown.requested().await,
+		None => future::pending().await,
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use rdkafka::message::OwnedHeaders;
+
+	fn message(topic: &str, partition: i32) -> KafkaMessage {
+		KafkaMessage {
+			topic: topic.to_string(),
+			partition,
+			..Default::default()
+		}
+	}
+
+	#[test]
+	fn test_partitions_are_spread_over_queues() {
+		let queues: HashSet<_> = (0..4).map(|p| message("metrics", p).queue(4)).collect();
+		assert_eq!(queues.len(), 4);
+
+		// The messages of a partition always go to the same queue.
+		assert_eq!(
+			message("metrics", 2).queue(4),
+			message("metrics", 2).queue(4)
+		);
+		assert_eq!(
+			message("metrics", 6).queue(4),
+			message("metrics", 2).queue(4)
+		);
+		assert_eq!(message("metrics", 3).queue(1), 0);
+	}
+
+	#[test]
+	fn test_water_marks() {
+		let water_marks = WaterMarks { high: 80, low: 20 };
+		assert!(!water_marks.should_pause(false, 79));
+		assert!(water_marks.should_pause(false, 80));
+		// Stays paused until the queues drained to the low-water mark.
+		assert!(water_marks.should_pause(true, 21));
+		assert!(!water_marks.should_pause(true, 20));
+
+		assert_eq!(WaterMarks::new(80, 20, 100).unwrap(), water_marks);
+		assert!(WaterMarks::new(80, 80, 100).is_err());
+		assert!(WaterMarks::new(20, 80, 100).is_err());
+		assert!(WaterMarks::new(120, 20, 100).is_err());
+	}
+
+	/// Consume `topics` in `group_id` from the beginning up to their end,
+	/// acking every message. Returns the number of consumed messages.
+	async fn replay(topics: &[&str], group_id: &str) -> usize {
+		let consumer: StreamConsumer<SubscriberContext> = ClientConfig::new()
+			.set("group.id", group_id)
+			.set("bootstrap.servers", "localhost:9092")
+			.set("enable.partition.eof", "true")
+			.set("enable.auto.commit", "false")
+			.set("auto.offset.reset", "earliest")
+			.create_with_context(SubscriberContext::default())
+			.expect("Consumer creation failed");
+		let kconsumer = KafkaConsumer::new_with_consumer(consumer, topics)
+			.with_start(Position::Beginning)
+			.with_end(Position::End);
+
+		let acknowledger = kconsumer.acknowledger();
+		let (tx, mut rx) = mpsc::channel::<KafkaMessage>(10);
+		let counter = task::spawn(async move {
+			let mut consumed = 0;
+			while let Some(kmessage) = rx.recv().await {
+				acknowledger.ack(&kmessage.ack());
+				consumed += 1;
+			}
+			consumed
+		});
+		kconsumer.consume(&[tx]).await;
+		counter.await.unwrap()
+	}
+
+	#[tokio::test(flavor = "multi_thread")]
+	async fn test_replay_fully_consumed_group() {
+		let topic = format!("replay-{}", uuid::Uuid::new_v4());
+		let group_id = format!("replay-{}", uuid::Uuid::new_v4());
+		let producer = KafkaProducer::new("localhost:9092");
+		for _ in 0..3 {
+			producer
+				.produce(BytesMut::from(&b"batch"[..]), &topic, OwnedHeaders::new())
+				.await
+				.unwrap();
+		}
+
+		assert_eq!(replay(&[&topic], &group_id).await, 3);
+		// The group committed the end of the topic, which doesn't keep
+		// another replay from the beginning from seeing every message.
+		assert_eq!(replay(&[&topic], &group_id).await, 3);
+	}
+
+	#[tokio::test(flavor = "multi_thread")]
+	async fn test_replay_topics_of_different_lengths() {
+		let short = format!("replay-short-{}", uuid::Uuid::new_v4());
+		let long = format!("replay-long-{}", uuid::Uuid::new_v4());
+		let group_id = format!("replay-{}", uuid::Uuid::new_v4());
+		let producer = KafkaProducer::new("localhost:9092");
+		for (topic, count) in [(&short, 1), (&long, 50)] {
+			for _ in 0..count {
+				producer
+					.produce(BytesMut::from(&b"batch"[..]), topic, OwnedHeaders::new())
+					.await
+					.unwrap();
+			}
+		}
+
+		// Reaching the end of partition 0 of the short topic doesn't end
+		// partition 0 of the long one.
+		assert_eq!(replay(&[&short, &long], &group_id).await, 51);
 	}
 }
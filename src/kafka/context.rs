@@ -0,0 +1,306 @@
+// MIT License
+//
+// Copyright (c) 2019 Ankur Srivastava
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use crate::{
+	kafka::{consumer::start_offset, offsets::OffsetTracker, Ack, Position},
+	metrics::Registry,
+};
+use log::{error, info, warn};
+use rdkafka::{
+	client::ClientContext,
+	consumer::{stream_consumer::StreamConsumer, CommitMode, Consumer, ConsumerContext, Rebalance},
+	statistics::Statistics,
+	topic_partition_list::{Offset, TopicPartitionList},
+};
+use std::{
+	collections::{BTreeSet, HashSet},
+	sync::{
+		atomic::{AtomicUsize, Ordering},
+		Arc, Condvar, Mutex, MutexGuard, Weak,
+	},
+	time::Duration,
+};
+use tokio::{sync::Notify, task};
+
+const LAG_METRIC: &str = "kafka-consumer-lag";
+
+/// How long a revoke waits for the in-flight messages of its partitions.
+pub const DEFAULT_DRAIN_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Consumer context which keeps track of the partition assignment and of
+/// the offsets handed to the sink.
+///
+/// Before partitions are revoked, it waits until the sink acknowledged the
+/// in-flight messages of those partitions and commits their offsets, so the
+/// next owner of a partition continues right where this consumer stopped.
+pub struct SubscriberContext {
+	rebalances: AtomicUsize,
+	assignment: Mutex<BTreeSet<(String, i32)>>,
+	offsets: Mutex<OffsetTracker>,
+	/// Signalled on every ack, for revokes waiting on in-flight messages.
+	acked: Condvar,
+	/// Notified on every ack, for the consume loop to commit.
+	commit_needed: Notify,
+	consumer: Mutex<Weak<StreamConsumer<SubscriberContext>>>,
+	drain_timeout: Duration,
+	metrics: Arc<Registry>,
+	/// Position at which partitions start, instead of the committed offset.
+	start: Mutex<Option<Position>>,
+	/// Partitions which were assigned at the start position.
+	started: Mutex<HashSet<(String, i32)>>,
+}
+
+impl Default for SubscriberContext {
+	fn default() -> SubscriberContext {
+		SubscriberContext::new(DEFAULT_DRAIN_TIMEOUT)
+	}
+}
+
+impl SubscriberContext {
+	/// Create a context which waits up to `drain_timeout` for in-flight
+	/// messages when partitions are revoked.
+	pub fn new(drain_timeout: Duration) -> SubscriberContext {
+		SubscriberContext {
+			rebalances: AtomicUsize::new(0),
+			assignment: Mutex::new(BTreeSet::new()),
+			offsets: Mutex::new(OffsetTracker::default()),
+			acked: Condvar::new(),
+			commit_needed: Notify::new(),
+			consumer: Mutex::new(Weak::new()),
+			drain_timeout,
+			metrics: Arc::new(Registry::default()),
+			start: Mutex::new(None),
+			started: Mutex::new(HashSet::new()),
+		}
+	}
+
+	/// Number of rebalances seen so far. Changes whenever partitions were
+	/// assigned or revoked.
+	pub fn rebalances(&self) -> usize {
+		self.rebalances.load(Ordering::SeqCst)
+	}
+
+	/// Topic partitions currently assigned to this consumer.
+	pub fn assignment(&self) -> Vec<(String, i32)> {
+		self.assignment.lock().unwrap().iter().cloned().collect()
+	}
+
+	/// Metrics of the consumer and of the sink it feeds.
+	pub fn metrics(&self) -> &Arc<Registry> {
+		&self.metrics
+	}
+
+	/// Give the context a handle to commit with during rebalances.
+	pub(crate) fn set_consumer(&self, consumer: Weak<StreamConsumer<SubscriberContext>>) {
+		*self.consumer.lock().unwrap() = consumer;
+	}
+
+	/// Start partitions at `start` when they are assigned for the first time.
+	pub(crate) fn set_start(&self, start: Position) {
+		*self.start.lock().unwrap() = Some(start);
+	}
+
+	/// Assign the partitions which are assigned for the first time at the
+	/// start position. Partitions assigned again after a rebalance continue
+	/// from the committed offsets.
+	///
+	/// Replaces the whole assignment, which assumes an eager assignment
+	/// strategy, like the default one.
+	fn assign_start(&self, tpl: &TopicPartitionList) {
+		let start = match *self.start.lock().unwrap() {
+			Some(start) => start,
+			None => return,
+		};
+		let consumer = match self.consumer.lock().unwrap().upgrade() {
+			Some(consumer) => consumer,
+			None => return,
+		};
+
+		let mut started = self.started.lock().unwrap();
+		let mut positioned = tpl.clone();
+		let mut moved = false;
+		for elem in tpl.elements() {
+			let (topic, partition) = (elem.topic(), elem.partition());
+			if !started.insert((topic.to_string(), partition)) {
+				continue;
+			}
+			let result = start_offset(consumer.as_ref(), start, topic, partition)
+				.and_then(|offset| Ok(positioned.set_partition_offset(topic, partition, offset)?));
+			match result {
+				Ok(()) => {
+					info!("Starting {}/{} at {:?}", topic, partition, start);
+					moved = true;
+				}
+				Err(e) => error!(
+					"Failed to resolve {:?} for {}/{}: {:?}",
+					start, topic, partition, e
+				),
+			}
+		}
+		if moved {
+			if let Err(e) = consumer.assign(&positioned) {
+				error!("Failed to assign the start position: {:?}", e);
+			}
+		}
+	}
+
+	pub(crate) fn offsets(&self) -> MutexGuard<'_, OffsetTracker> {
+		self.offsets.lock().unwrap()
+	}
+
+	/// Record that the sink is done with a message.
+	pub(crate) fn ack(&self, ack: &Ack) {
+		self.offsets()
+			.complete(&ack.topic, ack.partition, ack.offset);
+		self.acked.notify_all();
+		self.commit_needed.notify_one();
+	}
+
+	/// Wait until a message was acknowledged since the last call.
+	pub(crate) async fn wait_for_ack(&self) {
+		self.commit_needed.notified().await
+	}
+
+	/// Commit the given offsets with the consumer this context belongs to.
+	pub(crate) fn commit(&self, committable: Vec<(String, i32, i64)>, mode: CommitMode) {
+		if committable.is_empty() {
+			return;
+		}
+		let consumer = match self.consumer.lock().unwrap().upgrade() {
+			Some(consumer) => consumer,
+			None => return,
+		};
+
+		let mut tpl = TopicPartitionList::new();
+		for (topic, partition, offset) in committable {
+			if let Err(e) = tpl.add_partition_offset(&topic, partition, Offset::Offset(offset)) {
+				error!(
+					"Failed to add offset {} of {}/{}: {:?}",
+					offset, topic, partition, e
+				);
+			}
+		}
+		if let Err(e) = consumer.commit(&tpl, mode) {
+			error!("Failed to commit offset to kafka: {:?}", e);
+		}
+	}
+
+	/// Wait for the in-flight messages of the given partitions, e.g. revoked
+	/// ones, and commit them.
+	pub(crate) fn drain(&self, revoked: &[(String, i32)]) {
+		let offsets = self.offsets();
+		let (mut offsets, wait) = self
+			.acked
+			.wait_timeout_while(offsets, self.drain_timeout, |offsets| {
+				offsets.has_in_flight(revoked)
+			})
+			.unwrap();
+		if wait.timed_out() {
+			warn!(
+				"Gave up waiting for in-flight messages of {:?} after {:?}, they will be redelivered",
+				revoked, self.drain_timeout
+			);
+		}
+		let committable = offsets.revoke(revoked);
+		drop(offsets);
+		self.commit(committable, CommitMode::Sync);
+	}
+}
+
+fn partitions(tpl: &TopicPartitionList) -> Vec<(String, i32)> {
+	tpl.elements()
+		.iter()
+		.map(|elem| (elem.topic().to_string(), elem.partition()))
+		.collect()
+}
+
+impl ClientContext for SubscriberContext {
+	/// Report the lag of every assigned partition, every
+	/// `statistics.interval.ms`.
+	fn stats(&self, statistics: Statistics) {
+		let mut total = 0;
+		for (topic, topic_statistics) in statistics.topics.iter() {
+			for (partition, partition_statistics) in topic_statistics.partitions.iter() {
+				// Partition -1 is internal to librdkafka, and the lag is -1
+				// until it is known.
+				if *partition < 0 || partition_statistics.consumer_lag < 0 {
+					continue;
+				}
+				self.metrics.set(
+					LAG_METRIC,
+					&[("topic", topic), ("partition", &partition.to_string())],
+					partition_statistics.consumer_lag as f32,
+				);
+				total += partition_statistics.consumer_lag;
+			}
+		}
+		info!("Consumer lag of {}: {}", statistics.name, total);
+	}
+}
+
+impl ConsumerContext for SubscriberContext {
+	fn pre_rebalance(&self, rebalance: &Rebalance) {
+		if let Rebalance::Revoke(tpl) = rebalance {
+			let revoked = partitions(tpl);
+			info!("Draining partitions before they are revoked: {:?}", revoked);
+			// Acks are sent by other tasks, which must keep running while this
+			// thread blocks.
+			task::block_in_place(|| self.drain(&revoked));
+		}
+	}
+
+	fn post_rebalance(&self, rebalance: &Rebalance) {
+		if let Rebalance::Assign(tpl) = rebalance {
+			self.assign_start(tpl);
+		}
+		let mut assignment = self.assignment.lock().unwrap();
+		match rebalance {
+			Rebalance::Assign(tpl) => {
+				let assigned = partitions(tpl);
+				info!("Assigned partitions: {:?}", assigned);
+				assignment.extend(assigned);
+			}
+			Rebalance::Revoke(tpl) => {
+				let revoked = partitions(tpl);
+				info!("Revoked partitions: {:?}", revoked);
+				for partition in revoked.iter() {
+					assignment.remove(partition);
+					self.metrics.remove(
+						LAG_METRIC,
+						&[
+							("topic", &partition.0),
+							("partition", &partition.1.to_string()),
+						],
+					);
+				}
+			}
+			Rebalance::Error(e) => error!("Rebalance failed: {}", e),
+		}
+		info!("Current assignment: {:?}", *assignment);
+		self.metrics.set(
+			"kafka-consumer-assigned-partitions",
+			&[],
+			assignment.len() as f32,
+		);
+		self.rebalances.fetch_add(1, Ordering::SeqCst);
+	}
+}
@@ -0,0 +1,187 @@
+// MIT License
+//
+// Copyright (c) 2019 Ankur Srivastava
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use crate::{
+	errors::AppError,
+	kafka::{KafkaMessage, KafkaProducer},
+};
+use rdkafka::message::OwnedHeaders;
+use std::{collections::HashMap, str};
+
+/// Header with the reason why a message was dead-lettered.
+pub const DLQ_ERROR_HEADER: &str = "dlq-error";
+/// Headers with the position the message was originally consumed from.
+pub const DLQ_SOURCE_TOPIC_HEADER: &str = "dlq-source-topic";
+pub const DLQ_SOURCE_PARTITION_HEADER: &str = "dlq-source-partition";
+pub const DLQ_SOURCE_OFFSET_HEADER: &str = "dlq-source-offset";
+/// Header with the number of times handling the message was attempted,
+/// summed up over all replays.
+pub const DLQ_ATTEMPTS_HEADER: &str = "dlq-attempts";
+
+/// Publishes messages which couldn't be handled to a dead-letter topic.
+///
+/// The original payload and headers are kept as is, so signed and encrypted
+/// messages stay valid when they are replayed later on.
+pub struct DeadLetterQueue {
+	producer: KafkaProducer,
+	topic: String,
+}
+
+impl DeadLetterQueue {
+	/// Create a DeadLetterQueue publishing to the given topic.
+	///
+	/// # Examples
+	/// Basic usage:
+	///
+	/// ```rust norun
+	/// let dlq = DeadLetterQueue::new(KafkaProducer::new("localhost:9092"), "metrics-dlq");
+	/// ```
+	pub fn new(producer: KafkaProducer, topic: &str) -> DeadLetterQueue {
+		DeadLetterQueue {
+			producer,
+			topic: topic.to_string(),
+		}
+	}
+
+	/// Publish a message to the dead-letter topic along with the reason and
+	/// the number of attempts made to handle it.
+	pub async fn send(
+		&self,
+		kmessage: &KafkaMessage,
+		reason: &str,
+		attempts: u32,
+	) -> Result<(), AppError> {
+		let headers = to_owned_headers(dead_letter_headers(kmessage, reason, attempts));
+		self.producer
+			.produce(kmessage.payload.clone(), &self.topic, headers)
+			.await
+	}
+}
+
+/// Get the headers of a dead-lettered message: the original ones plus the
+/// reason, source position and attempt count.
+fn dead_letter_headers(
+	kmessage: &KafkaMessage,
+	reason: &str,
+	attempts: u32,
+) -> Vec<(String, Vec<u8>)> {
+	let previous_attempts = kmessage
+		.header(DLQ_ATTEMPTS_HEADER)
+		.and_then(|value| str::from_utf8(value).ok())
+		.and_then(|value| value.parse::<u32>().ok())
+		.unwrap_or(0);
+
+	let mut headers = replay_headers(&kmessage.headers);
+	headers.retain(|(name, _)| name != DLQ_ATTEMPTS_HEADER);
+	headers.extend(vec![
+		(DLQ_ERROR_HEADER.to_string(), reason.as_bytes().to_vec()),
+		(
+			DLQ_SOURCE_TOPIC_HEADER.to_string(),
+			kmessage.topic.as_bytes().to_vec(),
+		),
+		(
+			DLQ_SOURCE_PARTITION_HEADER.to_string(),
+			kmessage.partition.to_string().into_bytes(),
+		),
+		(
+			DLQ_SOURCE_OFFSET_HEADER.to_string(),
+			kmessage.offset.to_string().into_bytes(),
+		),
+		(
+			DLQ_ATTEMPTS_HEADER.to_string(),
+			(previous_attempts + attempts).to_string().into_bytes(),
+		),
+	]);
+	headers
+}
+
+/// Get the headers to replay a dead-lettered message with. The dead-letter
+/// headers are dropped, except for the attempt count.
+pub(crate) fn replay_headers(headers: &HashMap<String, Vec<u8>>) -> Vec<(String, Vec<u8>)> {
+	let mut headers: Vec<_> = headers
+		.iter()
+		.filter(|(name, _)| !name.starts_with("dlq-") || *name == DLQ_ATTEMPTS_HEADER)
+		.map(|(name, value)| (name.clone(), value.clone()))
+		.collect();
+	headers.sort();
+	headers
+}
+
+pub(crate) fn to_owned_headers(headers: Vec<(String, Vec<u8>)>) -> OwnedHeaders {
+	headers
+		.iter()
+		.fold(OwnedHeaders::new(), |owned, (name, value)| {
+			owned.add(name, value)
+		})
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn failed_message() -> KafkaMessage {
+		let mut headers = HashMap::new();
+		headers.insert("content-type".to_string(), b"avro/binary".to_vec());
+		KafkaMessage {
+			headers,
+			topic: "metrics".to_string(),
+			partition: 3,
+			offset: 42,
+			..Default::default()
+		}
+	}
+
+	#[test]
+	fn test_dead_letter_headers() {
+		let headers = dead_letter_headers(&failed_message(), "bad payload", 2);
+		let headers: HashMap<_, _> = headers.into_iter().collect();
+		assert_eq!(headers["content-type"], b"avro/binary");
+		assert_eq!(headers[DLQ_ERROR_HEADER], b"bad payload");
+		assert_eq!(headers[DLQ_SOURCE_TOPIC_HEADER], b"metrics");
+		assert_eq!(headers[DLQ_SOURCE_PARTITION_HEADER], b"3");
+		assert_eq!(headers[DLQ_SOURCE_OFFSET_HEADER], b"42");
+		assert_eq!(headers[DLQ_ATTEMPTS_HEADER], b"2");
+	}
+
+	#[test]
+	fn test_replayed_message_keeps_counting_attempts() {
+		let dead_lettered = dead_letter_headers(&failed_message(), "bad payload", 2);
+		let replayed = replay_headers(&dead_lettered.into_iter().collect());
+		assert_eq!(
+			replayed,
+			vec![
+				("content-type".to_string(), b"avro/binary".to_vec()),
+				(DLQ_ATTEMPTS_HEADER.to_string(), b"2".to_vec()),
+			]
+		);
+
+		let kmessage = KafkaMessage {
+			headers: replayed.into_iter().collect(),
+			..failed_message()
+		};
+		let headers: HashMap<_, _> = dead_letter_headers(&kmessage, "still bad", 1)
+			.into_iter()
+			.collect();
+		assert_eq!(headers[DLQ_ERROR_HEADER], b"still bad");
+		assert_eq!(headers[DLQ_ATTEMPTS_HEADER], b"3");
+	}
+}
@@ -0,0 +1,271 @@
+// MIT License
+//
+// Copyright (c) 2019 Ankur Srivastava
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use crate::{errors::AppError, kafka::KafkaMessage};
+use aes_gcm::{
+	aead::{Aead, AeadCore, KeyInit, OsRng, Payload},
+	Aes256Gcm, Nonce,
+};
+use serde::Deserialize;
+use std::{borrow::Cow, collections::HashMap, fs, path::Path, str};
+
+/// Name of the kafka header which carries the id of the encryption key.
+pub const ENCRYPTION_KEY_ID_HEADER: &str = "encryption-key-id";
+
+const NONCE_LEN: usize = 12;
+
+#[derive(Deserialize)]
+struct KeyringFile {
+	primary: String,
+	keys: HashMap<String, String>,
+}
+
+/// AES-256-GCM keys used to encrypt and decrypt encoded batches.
+///
+/// Keys are read from a json file holding hex encoded 256 bit keys by their id
+/// and the id of the key new payloads are encrypted with:
+///
+/// ```json
+/// {
+///   "primary": "2022-10",
+///   "keys": {
+///     "2022-09": "<64 hex characters>",
+///     "2022-10": "<64 hex characters>"
+///   }
+/// }
+/// ```
+///
+/// All keys stay active for decryption, so a key can be rotated by adding it
+/// to every subscriber first and making it the primary one afterwards.
+pub struct Keyring {
+	primary: String,
+	keys: HashMap<String, Aes256Gcm>,
+	accept_plaintext: bool,
+}
+
+impl Keyring {
+	/// Load a Keyring from a json file.
+	///
+	/// # Examples
+	/// Basic usage:
+	///
+	/// ```rust norun
+	/// let keyring = Keyring::load("certs/keyring.json").unwrap();
+	/// ```
+	pub fn load<P: AsRef<Path>>(path: P) -> Result<Keyring, AppError> {
+		Keyring::from_json(&fs::read_to_string(path)?)
+	}
+
+	fn from_json(json: &str) -> Result<Keyring, AppError> {
+		let file: KeyringFile = serde_json::from_str(json)?;
+
+		let mut keys = HashMap::new();
+		for (key_id, key) in file.keys.iter() {
+			let cipher = hex::decode(key)
+				.ok()
+				.and_then(|key| Aes256Gcm::new_from_slice(&key).ok())
+				.ok_or_else(|| {
+					AppError::Encryption(format!("key `{}` is not 64 hex characters", key_id))
+				})?;
+			keys.insert(key_id.clone(), cipher);
+		}
+
+		if !keys.contains_key(&file.primary) {
+			return Err(AppError::Encryption(format!(
+				"primary key `{}` is not in the keyring",
+				file.primary
+			)));
+		}
+		Ok(Keyring {
+			primary: file.primary,
+			keys,
+			accept_plaintext: false,
+		})
+	}
+
+	/// Pass messages without an encryption key id header through as is,
+	/// instead of rejecting them. Meant for the time it takes to roll out
+	/// encryption to every publisher.
+	///
+	/// # Examples
+	/// Basic usage:
+	///
+	/// ```rust norun
+	/// let keyring = Keyring::load("certs/keyring.json")?.with_plaintext(true);
+	/// ```
+	pub fn with_plaintext(mut self, accept_plaintext: bool) -> Keyring {
+		self.accept_plaintext = accept_plaintext;
+		self
+	}
+
+	/// Encrypt a payload with the primary key, returning the key id and the
+	/// envelope, which is the random nonce followed by the ciphertext.
+	pub fn encrypt(&self, plaintext: &[u8]) -> Result<(&str, Vec<u8>), AppError> {
+		let cipher = &self.keys[&self.primary];
+		let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+		let payload = Payload {
+			msg: plaintext,
+			aad: self.primary.as_bytes(),
+		};
+		let ciphertext = cipher
+			.encrypt(&nonce, payload)
+			.map_err(|_| AppError::Encryption("failed to encrypt the payload".to_string()))?;
+
+		let mut envelope = nonce.to_vec();
+		envelope.extend(ciphertext);
+		Ok((&self.primary, envelope))
+	}
+
+	/// Decrypt an envelope created by `encrypt` with the key of the given id.
+	pub fn decrypt(&self, key_id: &str, envelope: &[u8]) -> Result<Vec<u8>, AppError> {
+		let cipher = self
+			.keys
+			.get(key_id)
+			.ok_or_else(|| AppError::Encryption(format!("unknown encryption key `{}`", key_id)))?;
+		if envelope.len() < NONCE_LEN {
+			return Err(AppError::Encryption("payload is too short".to_string()));
+		}
+
+		let (nonce, ciphertext) = envelope.split_at(NONCE_LEN);
+		let payload = Payload {
+			msg: ciphertext,
+			aad: key_id.as_bytes(),
+		};
+		cipher
+			.decrypt(Nonce::from_slice(nonce), payload)
+			.map_err(|_| AppError::Encryption("failed to decrypt the payload".to_string()))
+	}
+}
+
+/// Get the plaintext payload of a message.
+///
+/// Messages without an encryption key id header are passed through as is,
+/// unless a keyring is configured which doesn't accept plaintext.
+pub fn decrypt_payload<'a>(
+	keyring: Option<&Keyring>,
+	message: &'a KafkaMessage,
+) -> Result<Cow<'a, [u8]>, AppError> {
+	let key_id = match (message.header(ENCRYPTION_KEY_ID_HEADER), keyring) {
+		(Some(key_id), _) => str::from_utf8(key_id).unwrap_or_default(),
+		(None, Some(keyring)) if !keyring.accept_plaintext => {
+			return Err(AppError::Encryption("message is not encrypted".to_string()))
+		}
+		(None, _) => return Ok(Cow::Borrowed(&message.payload)),
+	};
+	let keyring = keyring.ok_or_else(|| {
+		AppError::Encryption("message is encrypted but no keyring is configured".to_string())
+	})?;
+	Ok(Cow::Owned(keyring.decrypt(key_id, &message.payload)?))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use prost::bytes::BytesMut;
+
+	const KEYRING: &str = r#"
+	{
+		"primary": "new",
+		"keys": {
+			"old": "000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f",
+			"new": "1f1e1d1c1b1a191817161514131211100f0e0d0c0b0a09080706050403020100"
+		}
+	}
+	"#;
+
+	fn encrypted_message(keyring: &Keyring, plaintext: &[u8]) -> KafkaMessage {
+		let (key_id, envelope) = keyring.encrypt(plaintext).unwrap();
+		let mut message = KafkaMessage {
+			payload: BytesMut::from(&envelope[..]),
+			..Default::default()
+		};
+		message.headers.insert(
+			ENCRYPTION_KEY_ID_HEADER.to_string(),
+			key_id.as_bytes().to_vec(),
+		);
+		message
+	}
+
+	#[test]
+	fn test_encrypt_decrypt() {
+		let keyring = Keyring::from_json(KEYRING).unwrap();
+		let message = encrypted_message(&keyring, b"command line");
+		assert_eq!(message.header(ENCRYPTION_KEY_ID_HEADER), Some(&b"new"[..]));
+		assert!(!message.payload.windows(12).any(|w| w == b"command line"));
+
+		let plaintext = decrypt_payload(Some(&keyring), &message).unwrap();
+		assert_eq!(&plaintext[..], b"command line");
+	}
+
+	#[test]
+	fn test_decrypt_with_rotated_key() {
+		let old =
+			Keyring::from_json(&KEYRING.replace(r#""primary": "new""#, r#""primary": "old""#))
+				.unwrap();
+		let message = encrypted_message(&old, b"command line");
+
+		let keyring = Keyring::from_json(KEYRING).unwrap();
+		let plaintext = decrypt_payload(Some(&keyring), &message).unwrap();
+		assert_eq!(&plaintext[..], b"command line");
+	}
+
+	#[test]
+	fn test_reject_tampered_and_unknown() {
+		let keyring = Keyring::from_json(KEYRING).unwrap();
+
+		let mut message = encrypted_message(&keyring, b"command line");
+		let last = message.payload.len() - 1;
+		message.payload[last] ^= 1;
+		assert!(decrypt_payload(Some(&keyring), &message).is_err());
+
+		let mut message = encrypted_message(&keyring, b"command line");
+		message
+			.headers
+			.insert(ENCRYPTION_KEY_ID_HEADER.to_string(), b"old".to_vec());
+		assert!(decrypt_payload(Some(&keyring), &message).is_err());
+		assert!(decrypt_payload(None, &message).is_err());
+	}
+
+	#[test]
+	fn test_plaintext_passthrough() {
+		let message = KafkaMessage {
+			payload: BytesMut::from(&b"plain"[..]),
+			..Default::default()
+		};
+		assert_eq!(&decrypt_payload(None, &message).unwrap()[..], b"plain");
+
+		// Once a keyring is configured, plaintext has to be opted into.
+		let keyring = Keyring::from_json(KEYRING).unwrap();
+		assert!(decrypt_payload(Some(&keyring), &message).is_err());
+		let keyring = keyring.with_plaintext(true);
+		assert_eq!(
+			&decrypt_payload(Some(&keyring), &message).unwrap()[..],
+			b"plain"
+		);
+	}
+
+	#[test]
+	fn test_invalid_keyring() {
+		assert!(Keyring::from_json(r#"{"primary": "a", "keys": {}}"#).is_err());
+		assert!(Keyring::from_json(r#"{"primary": "a", "keys": {"a": "abc"}}"#).is_err());
+	}
+}
@@ -0,0 +1,143 @@
+// MIT License
+//
+// Copyright (c) 2019 Ankur Srivastava
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use crate::{errors::AppError, kafka::Subscriptions};
+use rdkafka::{
+	consumer::{BaseConsumer, Consumer},
+	topic_partition_list::{Offset, TopicPartitionList},
+};
+use std::time::Duration;
+
+/// Position of a consumer group in a partition.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PartitionLag {
+	pub topic: String,
+	pub partition: i32,
+	/// Offset committed by the group, if it committed one yet.
+	pub committed: Option<i64>,
+	pub low_watermark: i64,
+	pub high_watermark: i64,
+}
+
+impl PartitionLag {
+	/// Number of messages the group has yet to consume. Without a committed
+	/// offset, that is every message still in the partition.
+	pub fn lag(&self) -> i64 {
+		let consumed = self
+			.committed
+			.unwrap_or(self.low_watermark)
+			.max(self.low_watermark);
+		(self.high_watermark - consumed).max(0)
+	}
+}
+
+/// Get the lag of the consumer group of `consumer` in every partition of
+/// the topics covered by `subscriptions`.
+///
+/// The consumer doesn't need to be subscribed, so this doesn't disturb the
+/// running members of the group.
+///
+/// # Examples
+/// Basic usage:
+///
+/// ```rust norun
+/// let consumer: BaseConsumer = ClientConfig::new()
+///     .set("bootstrap.servers", "localhost:9092")
+///     .set("group.id", "kafka-rust-example")
+///     .create()?;
+/// let subscriptions = Subscriptions::parse(&["metrics".into()])?;
+/// let lags = consumer_lag(&consumer, &subscriptions, Duration::from_secs(10))?;
+/// ```
+pub fn consumer_lag(
+	consumer: &BaseConsumer,
+	subscriptions: &Subscriptions,
+	timeout: Duration,
+) -> Result<Vec<PartitionLag>, AppError> {
+	// Patterns need the metadata of every topic to be resolved.
+	let metadata = consumer.fetch_metadata(subscriptions.single_topic(), timeout)?;
+	let mut partitions: Vec<(String, i32)> = metadata
+		.topics()
+		.iter()
+		.filter(|metadata| subscriptions.matches(metadata.name()))
+		.flat_map(|metadata| {
+			metadata
+				.partitions()
+				.iter()
+				.map(move |partition| (metadata.name().to_string(), partition.id()))
+		})
+		.collect();
+	partitions.sort_unstable();
+
+	let mut tpl = TopicPartitionList::new();
+	for (topic, partition) in partitions.iter() {
+		tpl.add_partition(topic, *partition);
+	}
+	let committed = consumer.committed_offsets(tpl, timeout)?;
+
+	partitions
+		.into_iter()
+		.map(|(topic, partition)| {
+			let (low_watermark, high_watermark) =
+				consumer.fetch_watermarks(&topic, partition, timeout)?;
+			let committed = match committed
+				.find_partition(&topic, partition)
+				.map(|elem| elem.offset())
+			{
+				Some(Offset::Offset(offset)) => Some(offset),
+				_ => None,
+			};
+			Ok(PartitionLag {
+				topic,
+				partition,
+				committed,
+				low_watermark,
+				high_watermark,
+			})
+		})
+		.collect()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_lag() {
+		let mut lag = PartitionLag {
+			topic: "metrics".to_string(),
+			partition: 0,
+			committed: Some(120),
+			low_watermark: 100,
+			high_watermark: 130,
+		};
+		assert_eq!(lag.lag(), 10);
+
+		// Nothing committed yet, or the committed messages were deleted.
+		lag.committed = None;
+		assert_eq!(lag.lag(), 30);
+		lag.committed = Some(50);
+		assert_eq!(lag.lag(), 30);
+
+		lag.committed = Some(130);
+		assert_eq!(lag.lag(), 0);
+	}
+}
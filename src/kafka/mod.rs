@@ -1,4 +1,24 @@
 mod consumer;
+mod context;
+mod dead_letter;
+mod encryption;
+mod lag;
+mod offsets;
+mod position;
 mod producer;
-pub use consumer::KafkaConsumer;
+mod quarantine;
+mod signing;
+mod subscriptions;
+pub use consumer::{Ack, Acknowledger, KafkaConsumer, KafkaMessage, WaterMarks};
+pub use context::{SubscriberContext, DEFAULT_DRAIN_TIMEOUT};
+pub use dead_letter::{
+	DeadLetterQueue, DLQ_ATTEMPTS_HEADER, DLQ_ERROR_HEADER, DLQ_SOURCE_OFFSET_HEADER,
+	DLQ_SOURCE_PARTITION_HEADER, DLQ_SOURCE_TOPIC_HEADER,
+};
+pub use encryption::{decrypt_payload, Keyring, ENCRYPTION_KEY_ID_HEADER};
+pub use lag::{consumer_lag, PartitionLag};
+pub use position::Position;
 pub use producer::KafkaProducer;
+pub use quarantine::Quarantine;
+pub use signing::{SigningKeys, KEY_ID_HEADER, SIGNATURE_HEADER, SIGNED_HEADERS};
+pub use subscriptions::Subscriptions;
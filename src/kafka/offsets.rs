@@ -0,0 +1,238 @@
+// MIT License
+//
+// Copyright (c) 2019 Ankur Srivastava
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use log::debug;
+use std::collections::{BTreeSet, HashMap};
+
+/// Keeps track of the messages handed to the sink per partition, to find the
+/// offsets which are safe to commit.
+///
+/// An offset can only be committed once every message before it has been
+/// acknowledged, so a message which is still being written, or failed to be
+/// written, holds back the commits of its partition. After a restart the
+/// consumer resumes from there, which makes the delivery at-least-once.
+#[derive(Debug, Default)]
+pub struct OffsetTracker {
+	partitions: HashMap<(String, i32), PartitionOffsets>,
+}
+
+#[derive(Debug, Default)]
+struct PartitionOffsets {
+	/// Offsets handed to the sink which were not acknowledged yet.
+	in_flight: BTreeSet<i64>,
+	/// One past the highest offset which was acknowledged.
+	next: i64,
+	/// Offset which was committed last.
+	committed: i64,
+}
+
+impl OffsetTracker {
+	/// Record that the message at `offset` was handed to the sink.
+	pub fn dispatch(&mut self, topic: &str, partition: i32, offset: i64) {
+		self.partition(topic, partition, offset)
+			.in_flight
+			.insert(offset);
+	}
+
+	/// Record that the message at `offset` was acknowledged by the sink.
+	///
+	/// Acks of partitions which are not tracked, e.g. because they were
+	/// revoked in the meantime, are ignored. The partition may belong to
+	/// another consumer by now, which commits its offsets itself.
+	pub fn complete(&mut self, topic: &str, partition: i32, offset: i64) {
+		let offsets = match self.partitions.get_mut(&(topic.to_string(), partition)) {
+			Some(offsets) => offsets,
+			None => {
+				debug!(
+					"Ignoring the ack of offset {} of untracked partition {}/{}",
+					offset, topic, partition
+				);
+				return;
+			}
+		};
+		offsets.in_flight.remove(&offset);
+		offsets.next = offsets.next.max(offset + 1);
+	}
+
+	/// Record that the message at `offset` is done with without being handed
+	/// to the sink, e.g. because it failed verification.
+	pub fn skip(&mut self, topic: &str, partition: i32, offset: i64) {
+		self.dispatch(topic, partition, offset);
+		self.complete(topic, partition, offset);
+	}
+
+	/// Get the offsets to commit for every partition whose committable
+	/// offset moved since the last call.
+	pub fn take_committable(&mut self) -> Vec<(String, i32, i64)> {
+		let mut committable = vec![];
+		for ((topic, partition), offsets) in self.partitions.iter_mut() {
+			let offset = match offsets.in_flight.iter().next() {
+				Some(lowest_in_flight) => *lowest_in_flight,
+				None => offsets.next,
+			};
+			if offset > offsets.committed {
+				offsets.committed = offset;
+				committable.push((topic.clone(), *partition, offset));
+			}
+		}
+		committable
+	}
+
+	/// Check whether any of the given partitions has messages which were not
+	/// acknowledged yet.
+	pub fn has_in_flight(&self, partitions: &[(String, i32)]) -> bool {
+		partitions
+			.iter()
+			.filter_map(|key| self.partitions.get(key))
+			.any(|offsets| !offsets.in_flight.is_empty())
+	}
+
+	/// Stop tracking the given partitions, e.g. because they were revoked.
+	/// Returns the offsets of those partitions which still have to be
+	/// committed.
+	pub fn revoke(&mut self, partitions: &[(String, i32)]) -> Vec<(String, i32, i64)> {
+		let mut committable = vec![];
+		for (topic, partition) in partitions.iter() {
+			let offsets = match self.partitions.remove(&(topic.clone(), *partition)) {
+				Some(offsets) => offsets,
+				None => continue,
+			};
+			let offset = match offsets.in_flight.iter().next() {
+				Some(lowest_in_flight) => *lowest_in_flight,
+				None => offsets.next,
+			};
+			if offset > offsets.committed {
+				committable.push((topic.clone(), *partition, offset));
+			}
+		}
+		committable
+	}
+
+	/// Get the offsets of a partition, starting to track it at `offset` when
+	/// it is seen for the first time.
+	fn partition(&mut self, topic: &str, partition: i32, offset: i64) -> &mut PartitionOffsets {
+		self.partitions
+			.entry((topic.to_string(), partition))
+			.or_insert_with(|| PartitionOffsets {
+				next: offset,
+				committed: offset,
+				..Default::default()
+			})
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_commit_after_ack() {
+		let mut tracker = OffsetTracker::default();
+		tracker.dispatch("metrics", 0, 10);
+		tracker.dispatch("metrics", 0, 11);
+		assert!(tracker.take_committable().is_empty());
+
+		tracker.complete("metrics", 0, 10);
+		assert_eq!(
+			tracker.take_committable(),
+			vec![("metrics".to_string(), 0, 11)]
+		);
+		// Nothing moved, nothing to commit.
+		assert!(tracker.take_committable().is_empty());
+
+		tracker.complete("metrics", 0, 11);
+		assert_eq!(
+			tracker.take_committable(),
+			vec![("metrics".to_string(), 0, 12)]
+		);
+	}
+
+	#[test]
+	fn test_unacked_message_holds_back_partition() {
+		let mut tracker = OffsetTracker::default();
+		for offset in 0..3 {
+			tracker.dispatch("metrics", 0, offset);
+			tracker.dispatch("metrics", 1, offset);
+		}
+		tracker.complete("metrics", 0, 1);
+		tracker.complete("metrics", 0, 2);
+		for offset in 0..3 {
+			tracker.complete("metrics", 1, offset);
+		}
+
+		assert_eq!(
+			tracker.take_committable(),
+			vec![("metrics".to_string(), 1, 3)]
+		);
+
+		tracker.complete("metrics", 0, 0);
+		assert_eq!(
+			tracker.take_committable(),
+			vec![("metrics".to_string(), 0, 3)]
+		);
+	}
+
+	#[test]
+	fn test_revoke_returns_uncommitted_offsets() {
+		let mut tracker = OffsetTracker::default();
+		tracker.dispatch("metrics", 0, 10);
+		tracker.dispatch("metrics", 1, 20);
+		let revoked = vec![("metrics".to_string(), 0)];
+		assert!(tracker.has_in_flight(&revoked));
+
+		tracker.complete("metrics", 0, 10);
+		assert!(!tracker.has_in_flight(&revoked));
+		assert_eq!(
+			tracker.revoke(&revoked),
+			vec![("metrics".to_string(), 0, 11)]
+		);
+
+		// The revoked partition is forgotten, the other one is still tracked.
+		assert!(tracker.revoke(&revoked).is_empty());
+		assert!(tracker.has_in_flight(&[("metrics".to_string(), 1)]));
+		assert!(tracker.take_committable().is_empty());
+	}
+
+	#[test]
+	fn test_ack_after_revoke_is_ignored() {
+		let mut tracker = OffsetTracker::default();
+		tracker.dispatch("metrics", 0, 10);
+		let revoked = vec![("metrics".to_string(), 0)];
+		assert!(tracker.revoke(&revoked).is_empty());
+
+		// The write finishes after the drain timeout, when another consumer
+		// may own the partition already.
+		tracker.complete("metrics", 0, 10);
+		assert!(tracker.take_committable().is_empty());
+		assert!(tracker.revoke(&revoked).is_empty());
+	}
+
+	#[test]
+	fn test_skipped_message_is_committable() {
+		let mut tracker = OffsetTracker::default();
+		tracker.skip("metrics", 0, 10);
+		assert_eq!(
+			tracker.take_committable(),
+			vec![("metrics".to_string(), 0, 11)]
+		);
+	}
+}
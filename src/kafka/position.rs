@@ -0,0 +1,87 @@
+// MIT License
+//
+// Copyright (c) 2019 Ankur Srivastava
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use crate::errors::AppError;
+use chrono::DateTime;
+use std::str::FromStr;
+
+/// Position in a partition to start or stop consuming at.
+///
+/// Parsed from `beginning`, `end`, an offset such as `42` or an RFC 3339
+/// timestamp such as `2022-10-18T10:00:00Z`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Position {
+	Beginning,
+	End,
+	Offset(i64),
+	/// Milliseconds since the epoch, resolved to the first offset at or after
+	/// it with offsets_for_times.
+	Timestamp(i64),
+}
+
+impl FromStr for Position {
+	type Err = AppError;
+
+	fn from_str(s: &str) -> Result<Position, AppError> {
+		match s {
+			"beginning" => Ok(Position::Beginning),
+			"end" => Ok(Position::End),
+			_ => {
+				if let Ok(offset) = s.parse::<i64>() {
+					return Ok(Position::Offset(offset));
+				}
+				DateTime::parse_from_rfc3339(s)
+					.map(|timestamp| Position::Timestamp(timestamp.timestamp_millis()))
+					.map_err(|_| {
+						AppError::Position(format!(
+							"{} is neither beginning, end, an offset nor an RFC 3339 timestamp",
+							s
+						))
+					})
+			}
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_parse_position() {
+		assert_eq!(
+			"beginning".parse::<Position>().unwrap(),
+			Position::Beginning
+		);
+		assert_eq!("end".parse::<Position>().unwrap(), Position::End);
+		assert_eq!("42".parse::<Position>().unwrap(), Position::Offset(42));
+		assert_eq!(
+			"2022-10-18T10:00:00Z".parse::<Position>().unwrap(),
+			Position::Timestamp(1_666_087_200_000)
+		);
+		assert_eq!(
+			"2022-10-18T12:00:00+02:00".parse::<Position>().unwrap(),
+			Position::Timestamp(1_666_087_200_000)
+		);
+		assert!("yesterday".parse::<Position>().is_err());
+	}
+}
@@ -20,11 +20,13 @@
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
 // SOFTWARE.
 
+use crate::errors::AppError;
 use log::{debug, error};
 use prost::bytes::BytesMut;
 use rdkafka::{
 	config::ClientConfig,
-	producer::{FutureProducer, FutureRecord},
+	message::OwnedHeaders,
+	producer::{FutureProducer, FutureRecord, Producer},
 };
 use std::time::Duration;
 
@@ -72,14 +74,34 @@ impl KafkaProducer {
 		}
 	}
 
-	/// Publish a BytesMut record to a given topic on Kafka.
-	pub async fn produce(&self, data: BytesMut, topic: &str) {
-		let record = FutureRecord::to(topic).key("some key").payload(&data[..]);
+	/// Publish a BytesMut record along with its headers to a given topic on Kafka.
+	pub async fn produce(
+		&self,
+		data: BytesMut,
+		topic: &str,
+		headers: OwnedHeaders,
+	) -> Result<(), AppError> {
+		let record = FutureRecord::to(topic)
+			.key("some key")
+			.payload(&data[..])
+			.headers(headers);
 		// let produce_future: DeliveryFuture = self.producer.send(record, 0);
 		let produce_future = self.producer.send(record, Duration::from_millis(100)).await;
 		match produce_future {
-			Ok(message) => debug!("Status: {:?}", message),
-			Err(_) => error!("Future cancelled"),
-		};
+			Ok(message) => {
+				debug!("Status: {:?}", message);
+				Ok(())
+			}
+			Err((e, _)) => {
+				error!("Failed to publish to {}: {:?}", topic, e);
+				Err(e.into())
+			}
+		}
+	}
+
+	/// Wait up to `timeout` for the messages which are still queued in the
+	/// producer to be delivered.
+	pub fn flush(&self, timeout: Duration) {
+		self.producer.flush(timeout);
 	}
 }
@@ -0,0 +1,99 @@
+// MIT License
+//
+// Copyright (c) 2019 Ankur Srivastava
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use crate::{errors::AppError, kafka::KafkaMessage};
+use std::{
+	fs,
+	path::{Path, PathBuf},
+};
+
+/// Directory where messages which were rejected by the consumer are kept
+/// for later inspection.
+///
+/// Every message is stored as `<topic>-<partition>-<offset>.bin` containing
+/// the raw payload, next to a `.txt` file with the reason and the headers.
+pub struct Quarantine {
+	path: PathBuf,
+}
+
+impl Quarantine {
+	/// Create a Quarantine in the given directory, creating it if required.
+	///
+	/// # Examples
+	/// Basic usage:
+	///
+	/// ```rust norun
+	/// let quarantine = Quarantine::new("quarantine").unwrap();
+	/// ```
+	pub fn new<P: AsRef<Path>>(path: P) -> Result<Quarantine, AppError> {
+		fs::create_dir_all(&path)?;
+		Ok(Quarantine {
+			path: path.as_ref().to_path_buf(),
+		})
+	}
+
+	/// Store a rejected message along with the reason it was rejected.
+	pub fn store(&self, message: &KafkaMessage, reason: &str) -> Result<(), AppError> {
+		let name = format!("{}-{}-{}", message.topic, message.partition, message.offset);
+
+		let mut details = format!("reason: {}\n", reason);
+		for (header, value) in message.headers.iter() {
+			details.push_str(&format!(
+				"header {}: {}\n",
+				header,
+				String::from_utf8_lossy(value)
+			));
+		}
+
+		fs::write(self.path.join(format!("{}.bin", name)), &message.payload)?;
+		fs::write(self.path.join(format!("{}.txt", name)), details)?;
+		Ok(())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use prost::bytes::BytesMut;
+	use std::env;
+
+	#[test]
+	fn test_store() {
+		let path = env::temp_dir().join(format!("quarantine-{}", uuid::Uuid::new_v4()));
+		let quarantine = Quarantine::new(&path).unwrap();
+
+		let message = KafkaMessage {
+			payload: BytesMut::from(&b"payload"[..]),
+			topic: "metrics".to_string(),
+			partition: 2,
+			offset: 42,
+			..Default::default()
+		};
+		quarantine.store(&message, "message is not signed").unwrap();
+
+		assert_eq!(fs::read(path.join("metrics-2-42.bin")).unwrap(), b"payload");
+		let details = fs::read_to_string(path.join("metrics-2-42.txt")).unwrap();
+		assert!(details.contains("message is not signed"));
+
+		fs::remove_dir_all(path).unwrap();
+	}
+}
@@ -0,0 +1,230 @@
+// MIT License
+//
+// Copyright (c) 2019 Ankur Srivastava
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use crate::{
+	codec::CONTENT_TYPE_HEADER,
+	dedup::BATCH_ID_HEADER,
+	errors::AppError,
+	kafka::{KafkaMessage, ENCRYPTION_KEY_ID_HEADER},
+};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::str;
+
+/// Name of the kafka header which carries the id of the signing key.
+pub const KEY_ID_HEADER: &str = "signature-key-id";
+
+/// Name of the kafka header which carries the HMAC-SHA256 of the payload.
+pub const SIGNATURE_HEADER: &str = "signature";
+
+/// Headers covered by the signature along with the payload, as they decide
+/// how a payload is decrypted, decoded and deduplicated.
+pub const SIGNED_HEADERS: [&str; 3] = [
+	CONTENT_TYPE_HEADER,
+	ENCRYPTION_KEY_ID_HEADER,
+	BATCH_ID_HEADER,
+];
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Shared secrets used to sign and verify encoded batches.
+///
+/// Publishers sign with the first key, subscribers accept any of the keys,
+/// which allows rotating a key without stopping the publishers.
+#[derive(Clone)]
+pub struct SigningKeys {
+	keys: Vec<(String, Vec<u8>)>,
+}
+
+impl SigningKeys {
+	/// Create SigningKeys out of `key-id:secret` entries.
+	///
+	/// # Examples
+	/// Basic usage:
+	///
+	/// ```rust norun
+	/// let keys = SigningKeys::parse(&["publisher-1:some-secret".to_string()]).unwrap();
+	/// ```
+	pub fn parse(entries: &[String]) -> Result<SigningKeys, AppError> {
+		let keys = entries
+			.iter()
+			.map(|entry| match entry.split_once(':') {
+				Some((key_id, secret)) if !key_id.is_empty() && !secret.is_empty() => {
+					Ok((key_id.to_string(), secret.as_bytes().to_vec()))
+				}
+				// The entry is left out of the error, it might be a secret.
+				_ => Err(AppError::Signature(
+					"signing keys must look like `key-id:secret`".to_string(),
+				)),
+			})
+			.collect::<Result<Vec<_>, _>>()?;
+
+		if keys.is_empty() {
+			return Err(AppError::Signature("no signing keys given".to_string()));
+		}
+		Ok(SigningKeys { keys })
+	}
+
+	/// Sign a payload and the `SIGNED_HEADERS` among `headers` with the first
+	/// key, returning the key id and signature.
+	///
+	/// # Examples
+	/// Basic usage:
+	///
+	/// ```rust norun
+	/// let headers = [(CONTENT_TYPE_HEADER, b"application/x-protobuf".to_vec())];
+	/// let (key_id, signature) = keys.sign(&payload, &headers);
+	/// ```
+	pub fn sign(&self, payload: &[u8], headers: &[(&str, Vec<u8>)]) -> (&str, Vec<u8>) {
+		let (key_id, secret) = &self.keys[0];
+		let mut mac = HmacSha256::new_from_slice(secret).expect("HMAC can take a key of any size");
+		update(&mut mac, payload, |name| {
+			headers
+				.iter()
+				.find(|(header, _)| *header == name)
+				.map(|(_, value)| &value[..])
+		});
+		(key_id, mac.finalize().into_bytes().to_vec())
+	}
+
+	/// Verify the signature headers of a message against its payload.
+	pub fn verify(&self, message: &KafkaMessage) -> Result<(), AppError> {
+		let key_id = message
+			.header(KEY_ID_HEADER)
+			.ok_or_else(|| AppError::Signature("message is not signed".to_string()))?;
+		let signature = message
+			.header(SIGNATURE_HEADER)
+			.ok_or_else(|| AppError::Signature("message has no signature".to_string()))?;
+
+		let key_id = str::from_utf8(key_id).unwrap_or_default();
+		let secret = self
+			.keys
+			.iter()
+			.find(|(id, _)| id == key_id)
+			.map(|(_, secret)| secret)
+			.ok_or_else(|| AppError::Signature(format!("unknown signing key `{}`", key_id)))?;
+
+		let mut mac = HmacSha256::new_from_slice(secret).expect("HMAC can take a key of any size");
+		update(&mut mac, &message.payload, |name| message.header(name));
+		mac.verify_slice(signature)
+			.map_err(|_| AppError::Signature("signature does not match the payload".to_string()))
+	}
+}
+
+/// Feed the signed headers and the payload into `mac`. Each of them is
+/// length-prefixed and missing headers are marked as such, so that two
+/// different messages never feed the same bytes.
+fn update<'a>(mac: &mut HmacSha256, payload: &[u8], header: impl Fn(&str) -> Option<&'a [u8]>) {
+	for name in SIGNED_HEADERS.iter() {
+		match header(name) {
+			Some(value) => {
+				mac.update(&[1]);
+				mac.update(&(value.len() as u64).to_be_bytes());
+				mac.update(value);
+			}
+			None => mac.update(&[0]),
+		}
+	}
+	mac.update(&(payload.len() as u64).to_be_bytes());
+	mac.update(payload);
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use prost::bytes::BytesMut;
+
+	fn signed_message(keys: &SigningKeys, payload: &[u8]) -> KafkaMessage {
+		let headers = [
+			(CONTENT_TYPE_HEADER, b"application/x-protobuf".to_vec()),
+			(BATCH_ID_HEADER, b"some-batch".to_vec()),
+		];
+		let (key_id, signature) = keys.sign(payload, &headers);
+		let mut message = KafkaMessage {
+			payload: BytesMut::from(payload),
+			..Default::default()
+		};
+		for (name, value) in headers.iter() {
+			message.headers.insert(name.to_string(), value.clone());
+		}
+		message
+			.headers
+			.insert(KEY_ID_HEADER.to_string(), key_id.as_bytes().to_vec());
+		message
+			.headers
+			.insert(SIGNATURE_HEADER.to_string(), signature);
+		message
+	}
+
+	#[test]
+	fn test_sign_and_verify() {
+		let keys =
+			SigningKeys::parse(&["new:secret-2".to_string(), "old:secret-1".to_string()]).unwrap();
+		let message = signed_message(&keys, b"some payload");
+		assert!(keys.verify(&message).is_ok());
+
+		// Subscribers which still know the old key accept it during rotation.
+		let old = SigningKeys::parse(&["old:secret-1".to_string()]).unwrap();
+		assert!(keys.verify(&signed_message(&old, b"some payload")).is_ok());
+		assert!(old.verify(&message).is_err());
+	}
+
+	#[test]
+	fn test_reject_tampered_and_unsigned() {
+		let keys = SigningKeys::parse(&["key:secret".to_string()]).unwrap();
+
+		let mut message = signed_message(&keys, b"some payload");
+		message.payload = BytesMut::from(&b"other payload"[..]);
+		assert!(keys.verify(&message).is_err());
+
+		message.headers.clear();
+		assert!(keys.verify(&message).is_err());
+	}
+
+	#[test]
+	fn test_reject_tampered_headers() {
+		let keys = SigningKeys::parse(&["key:secret".to_string()]).unwrap();
+
+		let mut message = signed_message(&keys, b"some payload");
+		message
+			.headers
+			.insert(CONTENT_TYPE_HEADER.to_string(), b"text/plain".to_vec());
+		assert!(keys.verify(&message).is_err());
+
+		let mut message = signed_message(&keys, b"some payload");
+		message.headers.remove(BATCH_ID_HEADER);
+		assert!(keys.verify(&message).is_err());
+
+		let mut message = signed_message(&keys, b"some payload");
+		message
+			.headers
+			.insert(ENCRYPTION_KEY_ID_HEADER.to_string(), b"key".to_vec());
+		assert!(keys.verify(&message).is_err());
+	}
+
+	#[test]
+	fn test_parse_invalid_keys() {
+		assert!(SigningKeys::parse(&[]).is_err());
+		assert!(SigningKeys::parse(&["no-secret".to_string()]).is_err());
+		assert!(SigningKeys::parse(&[":secret".to_string()]).is_err());
+	}
+}
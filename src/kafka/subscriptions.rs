@@ -0,0 +1,108 @@
+// MIT License
+//
+// Copyright (c) 2019 Ankur Srivastava
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use crate::errors::AppError;
+use regex::Regex;
+
+/// Topics a consumer subscribes to. Like in librdkafka, entries starting
+/// with `^` are regular expressions, which cover every topic they match,
+/// including topics created later on.
+#[derive(Debug, Clone)]
+pub struct Subscriptions {
+	entries: Vec<String>,
+	patterns: Vec<Regex>,
+}
+
+impl Subscriptions {
+	/// Parse topic names and `^regex` patterns.
+	///
+	/// # Examples
+	/// Basic usage:
+	///
+	/// ```rust norun
+	/// let subscriptions = Subscriptions::parse(&["metrics".into(), "^metrics-.*".into()])?;
+	/// assert!(subscriptions.matches("metrics-team-a"));
+	/// ```
+	pub fn parse(entries: &[String]) -> Result<Subscriptions, AppError> {
+		let entries: Vec<String> = entries
+			.iter()
+			.map(|entry| entry.trim().to_string())
+			.filter(|entry| !entry.is_empty())
+			.collect();
+		if entries.is_empty() {
+			return Err(AppError::Subscription("no topic to subscribe to".into()));
+		}
+		let patterns = entries
+			.iter()
+			.filter(|entry| entry.starts_with('^'))
+			.map(|pattern| {
+				Regex::new(pattern)
+					.map_err(|e| AppError::Subscription(format!("{}: {}", pattern, e)))
+			})
+			.collect::<Result<_, _>>()?;
+		Ok(Subscriptions { entries, patterns })
+	}
+
+	/// Topic names and patterns, as passed to `Consumer::subscribe`.
+	pub fn entries(&self) -> Vec<&str> {
+		self.entries.iter().map(|entry| entry.as_str()).collect()
+	}
+
+	/// The single topic subscribed to, if there are no other topics or
+	/// patterns.
+	pub fn single_topic(&self) -> Option<&str> {
+		match self.entries.as_slice() {
+			[topic] if self.patterns.is_empty() => Some(topic),
+			_ => None,
+		}
+	}
+
+	/// Check whether `topic` is covered by the subscriptions. Patterns don't
+	/// match internal topics such as `__consumer_offsets`.
+	pub fn matches(&self, topic: &str) -> bool {
+		self.entries.iter().any(|entry| entry == topic)
+			|| (!topic.starts_with("__") && self.patterns.iter().any(|re| re.is_match(topic)))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_subscriptions() {
+		let subscriptions =
+			Subscriptions::parse(&["metrics".to_string(), " ^metrics-.*".to_string()]).unwrap();
+		assert_eq!(subscriptions.entries(), vec!["metrics", "^metrics-.*"]);
+		assert_eq!(subscriptions.single_topic(), None);
+		assert!(subscriptions.matches("metrics"));
+		assert!(subscriptions.matches("metrics-team-a"));
+		assert!(!subscriptions.matches("logs"));
+
+		let single = Subscriptions::parse(&["metrics".to_string()]).unwrap();
+		assert_eq!(single.single_topic(), Some("metrics"));
+		assert!(!single.matches("metrics-team-a"));
+
+		assert!(Subscriptions::parse(&["^metrics-(".to_string()]).is_err());
+		assert!(Subscriptions::parse(&[]).is_err());
+	}
+}
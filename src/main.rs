@@ -20,31 +20,42 @@
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
 // SOFTWARE.
 
-pub mod config;
-mod errors;
-pub mod generated;
-pub mod kafka;
-pub mod metrics;
-pub mod postgres;
-
-use prost::bytes::BytesMut;
-
-use config::Config;
-use uuid::Uuid;
+use kafka_rust_example::{
+	codec::{Codec, CONTENT_TYPE_HEADER},
+	config::Config,
+	dedup::BATCH_ID_HEADER,
+	generated::BatchMessage,
+	kafka::{
+		consumer_lag, DeadLetterQueue, KafkaConsumer, KafkaMessage, KafkaProducer, Keyring,
+		Position, Quarantine, SigningKeys, SubscriberContext, Subscriptions, WaterMarks,
+		ENCRYPTION_KEY_ID_HEADER, KEY_ID_HEADER, SIGNATURE_HEADER,
+	},
+	metrics::{MetricsGenerator, Registry},
+	pipeline::Pipeline,
+	postgres::{ConflictPolicy, DbClient},
+	retry::RetryPolicy,
+	shutdown::Shutdown,
+	sink::Sink,
+	supervisor::Supervisor,
+	timeliness::Timeliness,
+};
 
-use generated::BatchMessage;
-use kafka::{KafkaConsumer, KafkaProducer};
 use log::{debug, error, info};
-use metrics::MetricsGenerator;
-use postgres::DbClient;
-use prost::Message as PMessage;
+use prost::bytes::BytesMut;
 use rdkafka::{
 	config::{ClientConfig, RDKafkaLogLevel},
-	consumer::stream_consumer::StreamConsumer,
+	consumer::{stream_consumer::StreamConsumer, BaseConsumer},
+	message::OwnedHeaders,
 };
 use std::sync::Arc;
 use structopt::{clap::Shell, StructOpt};
-use tokio::{self, sync::mpsc, task, time, time::Duration};
+use tokio::{
+	self,
+	sync::{mpsc, Mutex},
+	task, time,
+	time::Duration,
+};
+use uuid::Uuid;
 
 #[derive(Debug, StructOpt)]
 pub enum Command {
@@ -58,7 +69,49 @@ pub enum Command {
 
 	#[structopt(name = "metrics-subscriber")]
 	/// Subscribe to a kafka-topic and write data to database.
-	MetricsSubscriber,
+	MetricsSubscriber {
+		/// Where to start instead of the committed offsets: an offset, an
+		/// RFC 3339 timestamp, beginning or end.
+		#[structopt(long)]
+		from: Option<Position>,
+	},
+
+	#[structopt(name = "replay")]
+	/// Write a range of the kafka-topic to the database again and stop.
+	Replay {
+		/// Where to start: an offset, an RFC 3339 timestamp, beginning or end.
+		#[structopt(long)]
+		from: Position,
+
+		/// Where to stop, not including it. Defaults to the current end of
+		/// the partitions.
+		#[structopt(long)]
+		until: Option<Position>,
+
+		/// Consumer group of the replay, separate from the subscribers.
+		#[structopt(long, default_value = "kafka-rust-example-replay")]
+		group_id: String,
+	},
+
+	#[structopt(name = "lag")]
+	/// Print the lag of the subscribers' consumer group per partition.
+	Lag {
+		/// Consumer group to check, defaults to APPLICATION_KAFKA_GROUP_ID.
+		#[structopt(long)]
+		group_id: Option<String>,
+	},
+
+	#[structopt(name = "dlq-replay")]
+	/// Publish the messages of the dead-letter topic to the kafka-topic again.
+	DlqReplay {
+		/// Consumer group which keeps track of the replayed messages.
+		#[structopt(long, default_value = "dlq-replay")]
+		group_id: String,
+
+		/// Stop once no message arrived for this many seconds.
+		#[structopt(long, default_value = "10")]
+		idle_timeout_secs: u64,
+	},
 }
 
 #[derive(Debug, StructOpt)]
@@ -67,15 +120,23 @@ pub struct Metrics {
 	pub command: Command,
 }
 
-/// Create a consumer based on the given configuration.
+/// Create the client configuration shared by all consumers.
 ///
 /// In case certificate path etc is provided then a sasl enabled client
-/// is created else a normal client.
-fn create_consumer(conf: Arc<Config>) -> KafkaConsumer {
+/// is configured else a normal client.
+fn consumer_config(conf: &Config) -> ClientConfig {
 	let is_tls = conf.kafka_ca_cert_path.is_some()
 		&& conf.kafka_password.is_some()
 		&& conf.kafka_username.is_some();
 
+	let mut client_config = ClientConfig::new();
+	client_config
+		.set("bootstrap.servers", &conf.kafka_brokers)
+		.set("enable.partition.eof", "false")
+		.set("session.timeout.ms", "6000")
+		.set("enable.auto.commit", "false")
+		.set_log_level(RDKafkaLogLevel::Debug);
+
 	if is_tls {
 		info!("TLS is enabled. Will try to create a secure client");
 		let username = conf
@@ -90,29 +151,50 @@ fn create_consumer(conf: Arc<Config>) -> KafkaConsumer {
 			.kafka_ca_cert_path
 			.as_deref()
 			.expect("Kafka ca certificate is required.");
-		let consumer: StreamConsumer = ClientConfig::new()
-			.set("group.id", "some-random-id")
-			.set("bootstrap.servers", &conf.kafka_brokers)
-			.set("enable.partition.eof", "false")
-			.set("session.timeout.ms", "6000")
-			.set("enable.auto.commit", "true")
+		client_config
 			.set("sasl.mechanisms", "PLAIN")
 			.set("security.protocol", "SASL_SSL")
 			.set("sasl.username", username)
 			.set("sasl.password", password)
-			.set("ssl.ca.location", ca_path)
-			.set_log_level(RDKafkaLogLevel::Debug)
-			.create()
-			.expect("Consumer creation failed");
-		return KafkaConsumer::new_with_consumer(consumer, &[&conf.kafka_topic]);
+			.set("ssl.ca.location", ca_path);
 	}
+	client_config
+}
+
+/// Create a consumer of `topics` based on the given configuration.
+///
+/// Without a `group_id`, the configured consumer group is joined. Consumers
+/// of any other group start from the earliest offset the group didn't commit,
+/// and report reaching the end of a partition.
+fn create_consumer(conf: Arc<Config>, topics: &[&str], group_id: Option<&str>) -> KafkaConsumer {
+	let mut client_config = consumer_config(&conf);
+	client_config.set(
+		"statistics.interval.ms",
+		conf.kafka_statistics_interval_ms.to_string(),
+	);
+	match group_id {
+		Some(group_id) => {
+			client_config
+				.set("group.id", group_id)
+				.set("auto.offset.reset", "earliest")
+				.set("enable.partition.eof", "true");
+		}
+		None => {
+			client_config
+				.set("group.id", &conf.kafka_group_id)
+				.set("auto.offset.reset", &conf.kafka_auto_offset_reset);
+			if let Some(instance_id) = &conf.kafka_group_instance_id {
+				client_config.set("group.instance.id", instance_id);
+			}
+		}
+	};
 
-	let group_id = Uuid::new_v4();
-	KafkaConsumer::new(
-		&conf.kafka_brokers,
-		&group_id.to_string(),
-		&[&conf.kafka_topic],
-	)
+	let consumer: StreamConsumer<SubscriberContext> = client_config
+		.create_with_context(SubscriberContext::new(Duration::from_secs(
+			conf.kafka_drain_timeout_secs,
+		)))
+		.expect("Consumer creation failed");
+	KafkaConsumer::new_with_consumer(consumer, topics)
 }
 
 /// Create a producer based on the given configuration.
@@ -156,86 +238,321 @@ fn create_producer(conf: Arc<Config>) -> KafkaProducer {
 /// Handle the message subscription command.
 ///
 /// This will subscribe to a kafka-topic on which metrics are being published.
-/// Then the incoming message is deserialized back to BatchMessage and
-/// published to an internal channel.
-/// Then this data is read and published to postgres.
-async fn handle_message_receiving(config: Arc<Config>, dbclient: DbClient) {
-	let (dbtx, mut dbrx) = mpsc::channel(100);
-	task::spawn(async move {
-		info!("Waiting to receive metrics-data on incoming queue.");
-		while let Some(raw_data) = dbrx.recv().await {
-			debug!("Received data on the incoming channel to write in database");
-			if let Ok(bmsg) = BatchMessage::decode(raw_data) {
-				if let Err(e) = dbclient.insert(&bmsg).await {
-					error!("Failed to write data to the db: {:?}", e);
-					let _ = dbclient.insert(&bmsg).await;
-				}
-			} else {
-				error!("Failed to decode the incoming message from kafka");
-			};
-		}
-	});
+/// Then the incoming message is published to the internal channel of the
+/// worker handling its partition.
+/// Then this data is deserialized back to BatchMessage, using the codec from
+/// its content-type header, and published to postgres.
+/// Once written, the message is acknowledged so that its offset gets committed.
+/// In exactly-once mode the offset is written to postgres along with the rows.
+/// Messages which can't be handled go to the dead-letter topic, if configured.
+/// On shutdown the consumption stops, the workers write what is left in their
+/// queues and the final offsets are committed.
+/// A `replay` writes the messages again even though they were written before,
+/// so neither exactly-once nor deduplication apply to it, and points which
+/// exist already are overwritten.
+async fn handle_message_receiving(
+	config: Arc<Config>,
+	mut dbclient: DbClient,
+	mut kconsumer: KafkaConsumer,
+	replay: bool,
+	shutdown: Shutdown,
+	supervisor: &Supervisor,
+) {
+	// Stored offsets and recorded batch ids would skip the replayed messages.
+	let exactly_once = config.kafka_exactly_once && !replay;
+	let (dedup_window_size, dedup_table) = if replay {
+		(0, false)
+	} else {
+		(config.dedup_window_size, config.dedup_table)
+	};
+	if replay {
+		dbclient = dbclient.with_conflict_policy(ConflictPolicy::Update);
+	}
+	let offset_store = dbclient.clone();
+	task::spawn(
+		kconsumer
+			.metrics()
+			.report(Duration::from_secs(config.metrics_report_interval_secs)),
+	);
+	let retry = RetryPolicy {
+		max_attempts: config.postgres_retry_max_attempts,
+		initial_backoff: Duration::from_millis(config.postgres_retry_initial_backoff_ms),
+		max_backoff: Duration::from_millis(config.postgres_retry_max_backoff_ms),
+	};
+	let mut sink = Sink::new(dbclient, kconsumer.acknowledger(), config.kafka_codec)
+		.with_retry(retry)
+		.with_exactly_once(exactly_once)
+		.with_shutdown(shutdown);
+	if let Some(path) = &config.kafka_keyring_path {
+		let keyring = Keyring::load(path).expect("Failed to load the kafka keyring");
+		sink = sink.with_keyring(keyring.with_plaintext(config.kafka_accept_plaintext));
+	}
+	sink = sink
+		.with_dedup(dedup_window_size, dedup_table)
+		.with_timeliness(Timeliness {
+			max_age: Duration::from_secs(config.late_data_max_age_secs),
+			late: config.late_data_policy,
+			max_skew: Duration::from_secs(config.future_data_max_skew_secs),
+			future: config.future_data_policy,
+		})
+		.with_metrics(kconsumer.metrics());
+	if let Some(path) = &config.pipeline_path {
+		sink = sink.with_pipeline(Pipeline::load(path).expect("Failed to load the pipeline"));
+	}
+	if let Some(topic) = &config.kafka_dead_letter_topic {
+		sink = sink.with_dead_letters(DeadLetterQueue::new(create_producer(config.clone()), topic));
+	}
 
+	// Each worker gets its own queue. All messages of a partition go through
+	// the same queue, so they are written in order.
+	if dedup_table {
+		let dbclient = offset_store.clone();
+		let retention = Duration::from_secs(config.dedup_table_retention_hours * 3600);
+		supervisor.spawn("dedup-pruner", move || {
+			prune_processed_batches(dbclient.clone(), retention)
+		});
+	}
+
+	let sink = Arc::new(sink);
+	let workers = config.kafka_subscriber_concurrency.max(1);
+	let mut queues = Vec::with_capacity(workers);
+	let mut handles = Vec::with_capacity(workers);
+	for worker in 0..workers {
+		let (dbtx, dbrx) = mpsc::channel::<KafkaMessage>(config.kafka_subscriber_queue_capacity);
+		queues.push(dbtx);
+		let sink = sink.clone();
+		let dbrx = Arc::new(Mutex::new(dbrx));
+		handles.push(
+			supervisor.spawn(&format!("sink-worker-{}", worker), move || {
+				sink.clone().run(worker, dbrx.clone())
+			}),
+		);
+	}
+
+	if !config.kafka_signing_keys.is_empty() {
+		info!("Signing keys are configured. Unsigned messages will be quarantined");
+		let keys =
+			SigningKeys::parse(&config.kafka_signing_keys).expect("Invalid kafka signing keys");
+		let quarantine = Quarantine::new(&config.kafka_quarantine_path)
+			.expect("Failed to create the quarantine directory");
+		kconsumer = kconsumer.with_signature_verification(keys, quarantine);
+	}
+	if exactly_once {
+		info!("Exactly-once is enabled. Offsets are stored in the database");
+		kconsumer = kconsumer.with_offset_store(offset_store);
+	}
 	debug!("Starting to cosume the data");
-	let conf = config.clone();
-	let kconsumer = create_consumer(conf);
-	kconsumer.consume(dbtx).await;
+	kconsumer.consume(&queues).await;
+
+	// Closing the queues lets the workers finish once they are empty.
+	drop(queues);
+	for handle in handles {
+		if let Err(e) = handle.await {
+			error!("Worker failed: {:?}", e);
+		}
+	}
+}
+
+/// Forget the batch ids recorded for deduplication once they are older than
+/// `retention`, checking every hour.
+async fn prune_processed_batches(dbclient: DbClient, retention: Duration) {
+	let mut interval = time::interval(Duration::from_secs(3600));
+	loop {
+		interval.tick().await;
+		match dbclient.prune_processed_batches(retention).await {
+			Ok(pruned) => debug!("Pruned {} batch ids", pruned),
+			Err(e) => error!("Failed to prune the batch ids: {:?}", e),
+		}
+	}
+}
+
+/// Handle the lag command.
+///
+/// Prints the committed offset, high watermark and lag of the consumer group
+/// in every partition of the kafka-topic.
+fn handle_lag(config: Arc<Config>, group_id: &str) -> Result<(), Box<dyn std::error::Error>> {
+	let consumer: BaseConsumer = consumer_config(&config)
+		.set("group.id", group_id)
+		.create()?;
+	let subscriptions = Subscriptions::parse(&config.subscriptions())?;
+	let lags = consumer_lag(&consumer, &subscriptions, Duration::from_secs(10))?;
+
+	println!(
+		"{:<20} {:>10} {:>15} {:>15} {:>10}",
+		"TOPIC", "PARTITION", "COMMITTED", "HIGH-WATERMARK", "LAG"
+	);
+	for lag in lags.iter() {
+		let committed = lag
+			.committed
+			.map(|offset| offset.to_string())
+			.unwrap_or_else(|| "-".to_string());
+		println!(
+			"{:<20} {:>10} {:>15} {:>15} {:>10}",
+			lag.topic,
+			lag.partition,
+			committed,
+			lag.high_watermark,
+			lag.lag()
+		);
+	}
+	println!(
+		"Total lag of {}: {}",
+		group_id,
+		lags.iter().map(|lag| lag.lag()).sum::<i64>()
+	);
+	Ok(())
+}
+
+/// Handle the dead-letter replay command.
+///
+/// Publishes the messages of the dead-letter topic to the kafka-topic again,
+/// e.g. after the subscriber was fixed to handle them.
+async fn handle_dead_letter_replay(
+	config: Arc<Config>,
+	group_id: &str,
+	idle_timeout: Duration,
+	shutdown: Shutdown,
+) {
+	let dead_letter_topic = config
+		.kafka_dead_letter_topic
+		.as_deref()
+		.expect("APPLICATION_KAFKA_DEAD_LETTER_TOPIC is required to replay it");
+	let kconsumer = create_consumer(config.clone(), &[dead_letter_topic], Some(group_id))
+		.with_shutdown(shutdown);
+	let kproducer = create_producer(config.clone());
+	match kconsumer
+		.replay(&kproducer, &config.kafka_topic, idle_timeout)
+		.await
+	{
+		Ok(replayed) => info!(
+			"Replayed {} messages from {} to {}",
+			replayed, dead_letter_topic, config.kafka_topic
+		),
+		Err(e) => error!("Failed to replay {}: {:?}", dead_letter_topic, e),
+	}
+}
+
+/// Collect the metrics of this machine every second, and send them encoded
+/// with `codec` to the channel, until the shutdown is requested.
+async fn collect_metrics(tx: mpsc::Sender<BytesMut>, codec: Codec, mut shutdown: Shutdown) {
+	debug!("Starting to produce the data");
+
+	let mut batch_messages = BatchMessage::default();
+	let mut interval = time::interval(Duration::from_millis(1000));
+	loop {
+		tokio::select! {
+			_ = interval.tick() => {}
+			_ = shutdown.requested() => {
+				info!("Stopped collecting metrics");
+				return;
+			}
+		}
+		// This is in its own scope so that it gets collected and
+		// ulimits are respected
+		{
+			let metrics_generator = MetricsGenerator::new();
+			let mut metrices = metrics_generator.used_memory();
+			let disks = metrics_generator.disk_stats();
+			// ...
+			// ... simulate some more statistics here and extend them all in metrics vector
+			metrices.extend(disks);
+			batch_messages.multiple_points = metrices;
+		}
+		let buffer = match codec.encode(&batch_messages) {
+			Ok(buffer) => buffer,
+			Err(e) => {
+				error!("Failed to encode the metrics batch: {:?}", e);
+				continue;
+			}
+		};
+
+		if let Err(e) = tx.send(buffer).await {
+			error!("receiver dropped {e}", e = e);
+			return;
+		};
+	}
 }
 
 /// Handle the message publishing command.
 ///
-/// This will generate metrics, convert it to protobuf messages of type
-/// BatchMessage and covert it to bytes
+/// This will generate metrics, convert it to messages of type BatchMessage
+/// and encode it to bytes with the configured codec.
 /// Send this message to an internal channel which is then consumed
 /// by a kafka producer to publish this message to a kafka-topic.
-async fn handle_message_publishing(config: Arc<Config>) {
+/// On shutdown the collection stops, the batches still in the channel are
+/// published and the producer is flushed.
+async fn handle_message_publishing(
+	config: Arc<Config>,
+	shutdown: Shutdown,
+	supervisor: &Supervisor,
+) {
 	// Create a mpsc channel to publish data to
 	let (tx, mut rx) = mpsc::channel(100);
-	let mut batch_messages = BatchMessage::default();
-
-	// Spawn an async task to collect metrics
-	task::spawn(async move {
-		debug!("Starting to produce the data");
-
-		let mut interval = time::interval(Duration::from_millis(1000));
-		loop {
-			interval.tick().await;
-			// This is in its own scope so that it gets collected and
-			// ulimits are respected
-			{
-				let metrics_generator = MetricsGenerator::new();
-				let mut metrices = metrics_generator.used_memory();
-				let disks = metrics_generator.disk_stats();
-				// ...
-				// ... simulate some more statistics here and extend them all in metrics vector
-				metrices.extend(disks);
-				batch_messages.multiple_points = metrices;
-			}
-			let mut buffer = BytesMut::with_capacity(batch_messages.encoded_len());
-			batch_messages.encode(&mut buffer).unwrap();
+	let codec = config.kafka_codec;
 
-			if let Err(e) = tx.send(buffer).await {
-				error!("receiver dropped {e}", e = e);
-				return;
-			};
-		}
+	// Spawn a supervised async task to collect metrics
+	supervisor.spawn("metrics-collector", move || {
+		collect_metrics(tx.clone(), codec, shutdown.clone())
 	});
 
 	let conf = config.clone();
 
 	// Create a kafka producer
 	let kproducer = create_producer(conf);
+	let signing_keys = if config.kafka_signing_keys.is_empty() {
+		None
+	} else {
+		Some(SigningKeys::parse(&config.kafka_signing_keys).expect("Invalid kafka signing keys"))
+	};
+	let keyring = config
+		.kafka_keyring_path
+		.as_ref()
+		.map(|path| Keyring::load(path).expect("Failed to load the kafka keyring"));
 
 	// Start reading data in the main thread
 	// and publish it to Kafka
-	while let Some(data) = rx.recv().await {
+	while let Some(mut data) = rx.recv().await {
 		debug!("Received data on the incoming channel");
-		kproducer.produce(data, &config.kafka_topic).await;
-		info!(
-			"Published data successfully on kafka topic: {}",
-			&config.kafka_topic
-		);
+		let mut header_values = vec![
+			(
+				CONTENT_TYPE_HEADER,
+				codec.content_type().as_bytes().to_vec(),
+			),
+			(BATCH_ID_HEADER, Uuid::new_v4().to_string().into_bytes()),
+		];
+		// The signature covers the encrypted payload, so tampering is detected
+		// without having to decrypt first.
+		if let Some(keyring) = &keyring {
+			match keyring.encrypt(&data) {
+				Ok((key_id, envelope)) => {
+					header_values.push((ENCRYPTION_KEY_ID_HEADER, key_id.as_bytes().to_vec()));
+					data = BytesMut::from(&envelope[..]);
+				}
+				Err(e) => {
+					error!("Failed to encrypt the metrics batch: {:?}", e);
+					continue;
+				}
+			}
+		}
+		if let Some(keys) = &signing_keys {
+			let (key_id, signature) = keys.sign(&data, &header_values);
+			header_values.push((KEY_ID_HEADER, key_id.as_bytes().to_vec()));
+			header_values.push((SIGNATURE_HEADER, signature));
+		}
+		let headers = header_values
+			.iter()
+			.fold(OwnedHeaders::new(), |headers, (name, value)| {
+				headers.add(name, &value[..])
+			});
+		match kproducer.produce(data, &config.kafka_topic, headers).await {
+			Ok(()) => info!(
+				"Published data successfully on kafka topic: {}",
+				&config.kafka_topic
+			),
+			Err(e) => error!("Failed to publish the metrics batch: {:?}", e),
+		}
 	}
+	kproducer.flush(Duration::from_secs(config.shutdown_timeout_secs));
 }
 
 #[tokio::main]
@@ -247,20 +564,114 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 	debug!("starting up");
 
 	let app_config = Arc::new(Config::new());
+	let subscriptions = Subscriptions::parse(&app_config.subscriptions())?;
+	let shutdown = Shutdown::listen();
+	let deadline = Duration::from_secs(app_config.shutdown_timeout_secs);
+	let supervise = |metrics: Arc<Registry>| {
+		Supervisor::new(
+			app_config.supervisor_policy,
+			Duration::from_secs(app_config.supervisor_max_backoff_secs),
+			metrics,
+			shutdown.clone(),
+		)
+	};
+	let water_marks = || {
+		WaterMarks::new(
+			app_config.kafka_pause_high_water_mark,
+			app_config.kafka_pause_low_water_mark,
+			app_config.kafka_subscriber_queue_capacity,
+		)
+	};
 
 	let dbclient = DbClient::from(
 		&app_config.postgres_database_url,
 		app_config.postgres_cert_path.as_deref(),
-	)?;
+	)?
+	.with_copy_threshold(app_config.postgres_copy_threshold)
+	.with_conflict_policy(app_config.postgres_conflict_policy);
 
 	match opt.command {
 		Command::MetricsPublisher => {
+			app_config.kafka_codec.check_encodable()?;
 			info!("Started metrics publishing to kafka-topic");
-			handle_message_publishing(app_config.clone()).await
+			let metrics = Arc::new(Registry::default());
+			task::spawn(
+				metrics
+					.clone()
+					.report(Duration::from_secs(app_config.metrics_report_interval_secs)),
+			);
+			let supervisor = supervise(metrics);
+			let publishing =
+				handle_message_publishing(app_config.clone(), shutdown.clone(), &supervisor);
+			shutdown.with_deadline(publishing, deadline).await;
+			supervisor.result()?;
 		}
-		Command::MetricsSubscriber => {
+		Command::MetricsSubscriber { from } => {
 			info!("Subscriber was invoked");
-			handle_message_receiving(app_config.clone(), dbclient).await
+			let mut kconsumer = create_consumer(app_config.clone(), &subscriptions.entries(), None)
+				.with_shutdown(shutdown.clone())
+				.with_backpressure(water_marks()?);
+			if let Some(from) = from {
+				kconsumer = kconsumer.with_start(from);
+			}
+			let supervisor = supervise(kconsumer.metrics());
+			let receiving = handle_message_receiving(
+				app_config.clone(),
+				dbclient,
+				kconsumer,
+				false,
+				shutdown.clone(),
+				&supervisor,
+			);
+			shutdown.with_deadline(receiving, deadline).await;
+			supervisor.result()?;
+		}
+		Command::Replay {
+			from,
+			until,
+			group_id,
+		} => {
+			info!("Replaying {:?} from {:?}", subscriptions.entries(), from);
+			let kconsumer = create_consumer(
+				app_config.clone(),
+				&subscriptions.entries(),
+				Some(&group_id),
+			)
+			.with_start(from)
+			.with_end(until.unwrap_or(Position::End))
+			.with_shutdown(shutdown.clone())
+			.with_backpressure(water_marks()?);
+			let supervisor = supervise(kconsumer.metrics());
+			let receiving = handle_message_receiving(
+				app_config.clone(),
+				dbclient,
+				kconsumer,
+				true,
+				shutdown.clone(),
+				&supervisor,
+			);
+			shutdown.with_deadline(receiving, deadline).await;
+			supervisor.result()?;
+			if !shutdown.is_requested() {
+				info!("Replay finished");
+			}
+		}
+		Command::DlqReplay {
+			group_id,
+			idle_timeout_secs,
+		} => {
+			info!("Replaying the dead-letter topic");
+			let replaying = handle_dead_letter_replay(
+				app_config.clone(),
+				&group_id,
+				Duration::from_secs(idle_timeout_secs),
+				shutdown.clone(),
+			);
+			shutdown.with_deadline(replaying, deadline).await;
+		}
+		Command::Lag { group_id } => {
+			let group_id = group_id.unwrap_or_else(|| app_config.kafka_group_id.clone());
+			handle_lag(app_config.clone(), &group_id)?;
 		}
 		Command::CheckDbData => {
 			let rows = dbclient.get_count().await?;
@@ -22,7 +22,13 @@
 
 use crate::generated::Message;
 use chrono::prelude::*;
+use log::info;
+use std::{
+	collections::{BTreeMap, HashMap},
+	sync::{Arc, Mutex},
+};
 use sysinfo::{DiskExt, System, SystemExt};
+use tokio::time::{self, Duration};
 
 #[derive(Default)]
 pub struct MetricsGenerator {
@@ -51,6 +57,7 @@ impl MetricsGenerator {
 			timestamp: timestamp.unwrap_or_else(|| Utc::now().timestamp_millis()),
 			name,
 			value,
+			labels: HashMap::new(),
 		}
 	}
 
@@ -68,7 +75,7 @@ impl MetricsGenerator {
 		for (idx, disk) in self.client.disks().iter().enumerate() {
 			let _metrics_name = format!("disk-available-space-{idx}", idx = idx);
 			let metrics = Self::create_metrics(
-				disk.name().to_os_string().into_string().unwrap(),
+				disk.name().to_string_lossy().into_owned(),
 				disk.available_space() as f32,
 				None,
 			);
@@ -98,10 +105,117 @@ impl MetricsGenerator {
 	}
 }
 
+type MetricKey = (String, Vec<(String, String)>);
+
+/// Gauges and counters describing the subscriber itself, e.g. whether its
+/// consumer is paused.
+#[derive(Debug, Default)]
+pub struct Registry {
+	values: Mutex<BTreeMap<MetricKey, f32>>,
+}
+
+impl Registry {
+	/// Set a gauge to the given value.
+	///
+	/// # Examples
+	/// Basic usage:
+	///
+	/// ```rust norun
+	/// let registry = Registry::default();
+	/// registry.set("kafka-consumer-paused", &[], 1.0);
+	/// ```
+	pub fn set(&self, name: &str, labels: &[(&str, &str)], value: f32) {
+		self.values
+			.lock()
+			.unwrap()
+			.insert(Self::key(name, labels), value);
+	}
+
+	/// Add to a counter, starting at 0.
+	pub fn increment(&self, name: &str, labels: &[(&str, &str)], by: f32) {
+		*self
+			.values
+			.lock()
+			.unwrap()
+			.entry(Self::key(name, labels))
+			.or_insert(0.0) += by;
+	}
+
+	/// Remove a gauge, e.g. of a partition which is no longer assigned.
+	pub fn remove(&self, name: &str, labels: &[(&str, &str)]) {
+		self.values.lock().unwrap().remove(&Self::key(name, labels));
+	}
+
+	/// Get the current value of a gauge or counter.
+	pub fn get(&self, name: &str, labels: &[(&str, &str)]) -> Option<f32> {
+		self.values
+			.lock()
+			.unwrap()
+			.get(&Self::key(name, labels))
+			.copied()
+	}
+
+	/// Get the current values as metrics messages.
+	pub fn snapshot(&self) -> Vec<Message> {
+		self.values
+			.lock()
+			.unwrap()
+			.iter()
+			.map(|((name, labels), value)| Message {
+				labels: labels.iter().cloned().collect(),
+				..MetricsGenerator::create_metrics(name.clone(), *value, None)
+			})
+			.collect()
+	}
+
+	/// Log the current values every `interval`.
+	pub async fn report(self: Arc<Self>, interval: Duration) {
+		let mut interval = time::interval(interval);
+		loop {
+			interval.tick().await;
+			for message in self.snapshot() {
+				info!(
+					"Subscriber metric {} {:?} = {}",
+					message.name, message.labels, message.value
+				);
+			}
+		}
+	}
+
+	fn key(name: &str, labels: &[(&str, &str)]) -> MetricKey {
+		let mut labels: Vec<_> = labels
+			.iter()
+			.map(|(name, value)| (name.to_string(), value.to_string()))
+			.collect();
+		labels.sort();
+		(name.to_string(), labels)
+	}
+}
+
 #[cfg(test)]
 mod tests {
 	use super::*;
 
+	#[test]
+	fn test_registry() {
+		let registry = Registry::default();
+		registry.set("kafka-consumer-paused", &[], 1.0);
+		registry.increment("restarts", &[("task", "sink"), ("worker", "0")], 1.0);
+		registry.increment("restarts", &[("worker", "0"), ("task", "sink")], 2.0);
+
+		assert_eq!(registry.get("kafka-consumer-paused", &[]), Some(1.0));
+		assert_eq!(
+			registry.get("restarts", &[("task", "sink"), ("worker", "0")]),
+			Some(3.0)
+		);
+		assert_eq!(registry.get("restarts", &[]), None);
+
+		let snapshot = registry.snapshot();
+		assert_eq!(snapshot.len(), 2);
+		assert_eq!(snapshot[1].name, "restarts");
+		assert_eq!(snapshot[1].labels["task"], "sink");
+	}
+
 	#[test]
 	fn test_used_memory() {
 		let mg = MetricsGenerator::new();
@@ -0,0 +1,259 @@
+// MIT License
+//
+// Copyright (c) 2019 Ankur Srivastava
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use crate::{
+	errors::AppError,
+	generated::{BatchMessage, Message},
+};
+use regex::Regex;
+use serde::Deserialize;
+use std::{convert::TryFrom, fs, path::Path};
+
+/// Regular expression read from a string in the pipeline file.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(try_from = "String")]
+pub struct Pattern(Regex);
+
+impl TryFrom<String> for Pattern {
+	type Error = regex::Error;
+
+	fn try_from(pattern: String) -> Result<Self, Self::Error> {
+		Regex::new(&pattern).map(Pattern)
+	}
+}
+
+/// A processing step applied to every point of a batch. Stages with a
+/// `name` pattern only touch points whose name matches it, the others touch
+/// every point.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "stage", rename_all = "kebab-case", deny_unknown_fields)]
+pub enum Stage {
+	/// Drop points whose name matches, and which carry `label`, with a value
+	/// matching `value` if given.
+	Drop {
+		name: Option<Pattern>,
+		label: Option<String>,
+		value: Option<Pattern>,
+	},
+
+	/// Rename points whose name matches, `to` may refer to capture groups
+	/// of the pattern like `$1`.
+	Rename { name: Pattern, to: String },
+
+	/// Set a label, replacing its value if it is set already.
+	AddLabel {
+		name: Option<Pattern>,
+		label: String,
+		value: String,
+	},
+
+	/// Remove a label.
+	RemoveLabel {
+		name: Option<Pattern>,
+		label: String,
+	},
+
+	/// Multiply the value, e.g. by 1/1048576 to turn bytes into MiB.
+	Scale { name: Option<Pattern>, factor: f32 },
+
+	/// Limit the value to the given bounds.
+	Clamp {
+		name: Option<Pattern>,
+		min: Option<f32>,
+		max: Option<f32>,
+	},
+}
+
+/// Chain of stages which transforms and filters the consumed batches before
+/// they are written to the database.
+///
+/// The stages run in order, so later stages see the names and labels
+/// written by earlier ones.
+#[derive(Debug, Clone, Default)]
+pub struct Pipeline {
+	stages: Vec<Stage>,
+}
+
+impl Pipeline {
+	/// Load a Pipeline from a json file holding a list of stages.
+	///
+	/// # Examples
+	/// Basic usage:
+	///
+	/// ```rust norun
+	/// // [{"stage": "drop", "name": "^swap_"}, {"stage": "scale", "name": "^memory_", "factor": 0.00000095367431640625}]
+	/// let pipeline = Pipeline::load("config/pipeline.json").unwrap();
+	/// ```
+	pub fn load<P: AsRef<Path>>(path: P) -> Result<Pipeline, AppError> {
+		Pipeline::from_json(&fs::read_to_string(path)?)
+	}
+
+	fn from_json(json: &str) -> Result<Pipeline, AppError> {
+		let stages: Vec<Stage> = serde_json::from_str(json)?;
+		for stage in stages.iter() {
+			if let Stage::Drop {
+				name: None,
+				label: None,
+				..
+			} = stage
+			{
+				return Err(AppError::Pipeline(
+					"a drop stage needs a name or a label".into(),
+				));
+			}
+		}
+		Ok(Pipeline { stages })
+	}
+
+	/// Run the points of `batch` through every stage.
+	pub fn apply(&self, batch: &mut BatchMessage) {
+		for stage in self.stages.iter() {
+			match stage {
+				Stage::Drop { name, label, value } => {
+					batch.multiple_points.retain(|point| {
+						let label_matches = match label {
+							None => true,
+							Some(label) => match (point.labels.get(label), value) {
+								(Some(found), Some(value)) => value.0.is_match(found),
+								(found, None) => found.is_some(),
+								(None, Some(_)) => false,
+							},
+						};
+						!(selects(name, point) && label_matches)
+					});
+				}
+				Stage::Rename { name, to } => {
+					for point in batch.multiple_points.iter_mut() {
+						if name.0.is_match(&point.name) {
+							point.name = name.0.replace(&point.name, to.as_str()).into_owned();
+						}
+					}
+				}
+				Stage::AddLabel { name, label, value } => {
+					for point in selected(name, batch) {
+						point.labels.insert(label.clone(), value.clone());
+					}
+				}
+				Stage::RemoveLabel { name, label } => {
+					for point in selected(name, batch) {
+						point.labels.remove(label);
+					}
+				}
+				Stage::Scale { name, factor } => {
+					for point in selected(name, batch) {
+						point.value *= factor;
+					}
+				}
+				Stage::Clamp { name, min, max } => {
+					for point in selected(name, batch) {
+						if let Some(min) = min {
+							point.value = point.value.max(*min);
+						}
+						if let Some(max) = max {
+							point.value = point.value.min(*max);
+						}
+					}
+				}
+			}
+		}
+	}
+}
+
+/// Check whether a stage with the `name` pattern touches `point`.
+fn selects(name: &Option<Pattern>, point: &Message) -> bool {
+	match name {
+		Some(pattern) => pattern.0.is_match(&point.name),
+		None => true,
+	}
+}
+
+/// Points of `batch` a stage with the `name` pattern touches.
+fn selected<'a>(
+	name: &'a Option<Pattern>,
+	batch: &'a mut BatchMessage,
+) -> impl Iterator<Item = &'a mut Message> {
+	batch
+		.multiple_points
+		.iter_mut()
+		.filter(move |point| selects(name, point))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn point(name: &str, value: f32, labels: &[(&str, &str)]) -> Message {
+		Message {
+			timestamp: 1,
+			name: name.to_string(),
+			value,
+			labels: labels
+				.iter()
+				.map(|(k, v)| (k.to_string(), v.to_string()))
+				.collect(),
+		}
+	}
+
+	#[test]
+	fn test_pipeline() {
+		let pipeline = Pipeline::from_json(
+			r#"[
+				{"stage": "drop", "name": "^swap_"},
+				{"stage": "drop", "label": "mount", "value": "^/snap/"},
+				{"stage": "rename", "name": "^used_(.*)$", "to": "${1}_used"},
+				{"stage": "scale", "name": "^memory_", "factor": 0.0009765625},
+				{"stage": "clamp", "name": "^cpu$", "min": 0, "max": 100},
+				{"stage": "add-label", "label": "env", "value": "prod"},
+				{"stage": "remove-label", "name": "^disk$", "label": "host"}
+			]"#,
+		)
+		.unwrap();
+		let mut batch = BatchMessage {
+			multiple_points: vec![
+				point("swap_used", 1.0, &[]),
+				point("disk", 5.0, &[("mount", "/snap/core"), ("host", "a")]),
+				point("disk", 6.0, &[("mount", "/"), ("host", "a")]),
+				point("used_memory", 1024.0, &[]),
+				point("memory_total", 2048.0, &[]),
+				point("cpu", 120.0, &[]),
+			],
+		};
+		pipeline.apply(&mut batch);
+
+		assert_eq!(
+			batch.multiple_points,
+			vec![
+				point("disk", 6.0, &[("mount", "/"), ("env", "prod")]),
+				point("memory_used", 1.0, &[("env", "prod")]),
+				point("memory_total", 2.0, &[("env", "prod")]),
+				point("cpu", 100.0, &[("env", "prod")]),
+			]
+		);
+	}
+
+	#[test]
+	fn test_invalid_pipeline() {
+		assert!(Pipeline::from_json(r#"[{"stage": "drop"}]"#).is_err());
+		assert!(Pipeline::from_json(r#"[{"stage": "rename", "name": "(", "to": "x"}]"#).is_err());
+		assert!(Pipeline::from_json(r#"[{"stage": "explode"}]"#).is_err());
+	}
+}
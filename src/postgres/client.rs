@@ -23,17 +23,146 @@
 use crate::{
 	errors::AppError,
 	generated::{BatchMessage, Message},
+	timeliness::OutOfRange,
 };
 use chrono::prelude::*;
-use deadpool_postgres::{Manager, Pool};
+use deadpool_postgres::{Manager, Pool, Transaction};
 use log::info;
 use native_tls::{Certificate, TlsConnector};
 use postgres_native_tls::MakeTlsConnector;
-use std::fs;
-use tokio_postgres::Config;
+use prost::bytes::Bytes;
+use serde::Deserialize;
+use std::{collections::HashMap, fs, time::Duration};
+use tokio_postgres::{
+	binary_copy::BinaryCopyInWriter,
+	types::{Json, ToSql, Type},
+	Config, CopyInSink,
+};
+use uuid::Uuid;
+
+/// A batch which can't be written to the metrics table, along with why.
+#[derive(Debug)]
+pub struct QuarantinedBatch<'a> {
+	pub topic: &'a str,
+	pub partition: i32,
+	pub offset: i64,
+	/// Content type of the payload, to decode it again later on.
+	pub content_type: &'a str,
+	/// Decrypted payload of the kafka message.
+	pub payload: &'a [u8],
+	pub error: String,
+	pub attempts: u32,
+}
+
+/// Batches with at least this many points are written with a binary COPY.
+pub const DEFAULT_COPY_THRESHOLD: usize = 1000;
+
+/// Statement which streams rows into the metrics table.
+const COPY_METRICS: &str = "COPY metrics (timestamp, name, value, labels) FROM STDIN BINARY";
+
+/// Statement which streams rows into the staging table.
+const COPY_STAGING: &str =
+	"COPY metrics_staging (timestamp, name, value, labels) FROM STDIN BINARY";
+
+/// Session local table for the rows of a COPY, which can't skip or update
+/// conflicting rows by itself. The rows are numbered in the order of the
+/// batch.
+const CREATE_STAGING: &str = "CREATE TEMPORARY TABLE IF NOT EXISTS metrics_staging \
+	(timestamp TIMESTAMPTZ NOT NULL, name TEXT NOT NULL, value DOUBLE PRECISION NOT NULL, \
+	labels JSONB NOT NULL, n BIGSERIAL) ON COMMIT DELETE ROWS";
+
+/// Points of a batch passed as one array per column, numbered in order.
+const UNNEST_POINTS: &str = "UNNEST($1::timestamptz[], $2::text[], $3::float8[], $4::jsonb[]) \
+	WITH ORDINALITY AS points (timestamp, name, value, labels, n)";
+
+/// Points of a batch written to the staging table.
+const STAGED_POINTS: &str = "metrics_staging AS points";
+
+/// What happens when a point is written again, i.e. there is a row with the
+/// same name, labels and timestamp already.
+#[derive(Deserialize, Debug, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum ConflictPolicy {
+	/// Fail the whole batch.
+	#[default]
+	Error,
+
+	/// Keep the existing row.
+	Nothing,
+
+	/// Overwrite the value of the existing row.
+	Update,
+}
+
+impl ConflictPolicy {
+	/// Statement which inserts `points` into the metrics table.
+	fn insert_statement(&self, points: &str) -> String {
+		match self {
+			ConflictPolicy::Error => format!(
+				"INSERT INTO metrics (timestamp, name, value, labels) \
+				 SELECT timestamp, name, value, labels FROM {}",
+				points
+			),
+			ConflictPolicy::Nothing => format!(
+				"INSERT INTO metrics (timestamp, name, value, labels) \
+				 SELECT timestamp, name, value, labels FROM {} \
+				 ON CONFLICT (name, labels_hash, timestamp) DO NOTHING",
+				points
+			),
+			// A statement can't update the same row twice, so only the last
+			// of the points with the same key is written.
+			ConflictPolicy::Update => format!(
+				"INSERT INTO metrics (timestamp, name, value, labels) \
+				 SELECT DISTINCT ON (name, md5(labels::text), timestamp) \
+				 timestamp, name, value, labels FROM {} \
+				 ORDER BY name, md5(labels::text), timestamp, n DESC \
+				 ON CONFLICT (name, labels_hash, timestamp) DO UPDATE SET value = EXCLUDED.value",
+				points
+			),
+		}
+	}
+}
+
+/// Statement which inserts out of range points, passed as one array per column.
+const INSERT_OUT_OF_RANGE_METRICS: &str =
+	"INSERT INTO out_of_range_metrics (timestamp, name, value, labels, reason) \
+	SELECT * FROM UNNEST($1::timestamptz[], $2::text[], $3::float8[], $4::jsonb[], $5::text[])";
+
+/// Points as one array per column, to insert a whole batch with UNNEST.
+#[derive(Default)]
+struct Columns<'a> {
+	timestamps: Vec<DateTime<Utc>>,
+	names: Vec<&'a str>,
+	values: Vec<f64>,
+	labels: Vec<Json<&'a HashMap<String, String>>>,
+}
+
+/// Convert the timestamp of a point, in milliseconds since the epoch.
+fn point_time(message: &Message) -> Result<DateTime<Utc>, AppError> {
+	Utc.timestamp_millis_opt(message.timestamp)
+		.single()
+		.ok_or(AppError::Timestamp(message.timestamp))
+}
+
+impl<'a> Columns<'a> {
+	fn push(&mut self, message: &'a Message) -> Result<(), AppError> {
+		self.timestamps.push(point_time(message)?);
+		self.names.push(&message.name);
+		self.values.push(message.value as f64);
+		self.labels.push(Json(&message.labels));
+		Ok(())
+	}
+
+	fn params(&self) -> Vec<&(dyn ToSql + Sync)> {
+		vec![&self.timestamps, &self.names, &self.values, &self.labels]
+	}
+}
 
+#[derive(Clone)]
 pub struct DbClient {
 	pool: Pool,
+	copy_threshold: usize,
+	conflict_policy: ConflictPolicy,
 }
 
 impl DbClient {
@@ -61,6 +190,8 @@ impl DbClient {
 			.expect("Failed to create a pool");
 		DbClient {
 			pool: connection_pool,
+			copy_threshold: DEFAULT_COPY_THRESHOLD,
+			conflict_policy: ConflictPolicy::default(),
 		}
 	}
 
@@ -88,7 +219,79 @@ impl DbClient {
 			Pool::builder(mgr).max_size(16).build()?
 		};
 
-		Ok(DbClient { pool })
+		Ok(DbClient {
+			pool,
+			copy_threshold: DEFAULT_COPY_THRESHOLD,
+			conflict_policy: ConflictPolicy::default(),
+		})
+	}
+
+	/// Write batches of at least `copy_threshold` points with a binary COPY,
+	/// instead of a single INSERT of arrays. 0 disables COPY.
+	///
+	/// # Examples
+	/// Basic usage:
+	///
+	/// ```rust norun
+	/// let client = DbClient::from(conn_string, None)?.with_copy_threshold(500);
+	/// ```
+	pub fn with_copy_threshold(mut self, copy_threshold: usize) -> DbClient {
+		self.copy_threshold = copy_threshold;
+		self
+	}
+
+	/// Decide what happens to points which were written before, the default
+	/// fails the batch.
+	///
+	/// # Examples
+	/// Basic usage:
+	///
+	/// ```rust norun
+	/// let client = DbClient::from(conn_string, None)?.with_conflict_policy(ConflictPolicy::Update);
+	/// ```
+	pub fn with_conflict_policy(mut self, conflict_policy: ConflictPolicy) -> DbClient {
+		self.conflict_policy = conflict_policy;
+		self
+	}
+
+	/// Check whether a batch is large enough to be written with COPY.
+	fn uses_copy(&self, messages: &BatchMessage) -> bool {
+		self.copy_threshold > 0 && messages.multiple_points.len() >= self.copy_threshold
+	}
+
+	/// Write the points of a batch within `transaction`, with a binary COPY
+	/// for large batches and a single INSERT of one array per column
+	/// otherwise.
+	async fn write_rows(
+		&self,
+		transaction: &Transaction<'_>,
+		messages: &BatchMessage,
+	) -> Result<(), AppError> {
+		if messages.multiple_points.is_empty() {
+			return Ok(());
+		}
+		if self.uses_copy(messages) {
+			if self.conflict_policy == ConflictPolicy::Error {
+				copy_rows(transaction.copy_in(COPY_METRICS).await?, messages).await?;
+				return Ok(());
+			}
+			transaction.batch_execute(CREATE_STAGING).await?;
+			copy_rows(transaction.copy_in(COPY_STAGING).await?, messages).await?;
+			let stmt = transaction
+				.prepare_cached(&self.conflict_policy.insert_statement(STAGED_POINTS))
+				.await?;
+			transaction.execute(&stmt, &[]).await?;
+			return Ok(());
+		}
+		let stmt = transaction
+			.prepare_cached(&self.conflict_policy.insert_statement(UNNEST_POINTS))
+			.await?;
+		let mut columns = Columns::default();
+		for message in messages.multiple_points.iter() {
+			columns.push(message)?;
+		}
+		transaction.execute(&stmt, &columns.params()).await?;
+		Ok(())
 	}
 
 	fn create_tls_connection(path: &str) -> Result<MakeTlsConnector, AppError> {
@@ -119,7 +322,9 @@ impl DbClient {
 	/// ```
 	pub async fn get_count(&self) -> Result<i64, AppError> {
 		let client = self.pool.get().await?;
-		let stmt = client.prepare("SELECT COUNT(*) FROM metrics").await?;
+		let stmt = client
+			.prepare_cached("SELECT COUNT(*) FROM metrics")
+			.await?;
 		let rows = client.query(&stmt, &[]).await?;
 		let value: i64 = rows[0].get(0);
 		Ok(value)
@@ -138,20 +343,10 @@ impl DbClient {
 	/// // use this client from this point on.
 	/// ```
 	pub async fn insert(&self, messages: &BatchMessage) -> Result<(), AppError> {
-		let client = self.pool.get().await?;
-		let stmt = client
-			.prepare("INSERT INTO metrics (timestamp, name, value) VALUES ($1, $2, $3)")
-			.await?;
-
-		for message in messages.multiple_points.iter() {
-			let ts = DateTime::<Utc>::from_utc(
-				NaiveDateTime::from_timestamp_opt(message.timestamp, 0).unwrap(),
-				Utc,
-			);
-			client
-				.execute(&stmt, &[&ts, &message.name, &(message.value as f64)])
-				.await?;
-		}
+		let mut client = self.pool.get().await?;
+		let transaction = client.transaction().await?;
+		self.write_rows(&transaction, messages).await?;
+		transaction.commit().await?;
 		info!("Published data to db");
 		Ok(())
 	}
@@ -168,20 +363,240 @@ impl DbClient {
 	pub async fn insert_message(&self, message: &Message) -> Result<(), AppError> {
 		let client = self.pool.get().await?;
 		let stmt = client
-			.prepare("INSERT INTO metrics (timestamp, name, value) VALUES ($1, $2, $3)")
+			.prepare_cached(&self.conflict_policy.insert_statement(UNNEST_POINTS))
 			.await?;
+		let mut columns = Columns::default();
+		columns.push(message)?;
+		client.execute(&stmt, &columns.params()).await?;
+		info!("Published data to db");
+		Ok(())
+	}
 
-		let ts = DateTime::<Utc>::from_utc(
-			NaiveDateTime::from_timestamp_opt(message.timestamp, 0).unwrap(),
-			Utc,
-		);
+	/// Write points outside of the accepted time range within `transaction`
+	/// into the out_of_range_metrics table, along with the reason.
+	async fn write_out_of_range(
+		&self,
+		transaction: &Transaction<'_>,
+		points: &[(OutOfRange, Message)],
+	) -> Result<(), AppError> {
+		if points.is_empty() {
+			return Ok(());
+		}
+		let stmt = transaction
+			.prepare_cached(INSERT_OUT_OF_RANGE_METRICS)
+			.await?;
+		let mut columns = Columns::default();
+		let mut reasons = Vec::with_capacity(points.len());
+		for (reason, message) in points.iter() {
+			columns.push(message)?;
+			reasons.push(reason.as_str());
+		}
+		let mut params = columns.params();
+		params.push(&reasons);
+		transaction.execute(&stmt, &params).await?;
+		info!("Published {} out of range points to db", points.len());
+		Ok(())
+	}
+
+	/// Insert a batch message together with the kafka position it was read
+	/// from, in a single transaction.
+	///
+	/// Returns false without writing any rows if the offset was stored
+	/// before, i.e. the message is a redelivery.
+	///
+	/// # Examples
+	///
+	/// ```rust norun
+	/// let client = DBClient::new("localhost", "5432", "username", "password", "metrics");
+	/// let batch_message = BatchMessage::default();
+	/// client.insert_with_offset(&batch_message, "metrics", 0, 42).await.unwrap();
+	/// ```
+	pub async fn insert_with_offset(
+		&self,
+		messages: &BatchMessage,
+		topic: &str,
+		partition: i32,
+		offset: i64,
+	) -> Result<bool, AppError> {
+		self.insert_once(messages, &[], None, Some((topic, partition, offset)))
+			.await
+	}
+
+	/// Insert a batch message in a single transaction, unless it was written
+	/// before.
+	///
+	/// The `routed` points, which are outside of the accepted time range, go
+	/// to the out_of_range_metrics table in the same transaction. A
+	/// `batch_id` is recorded in the processed_batches table, and batches
+	/// whose id was recorded before are skipped. A kafka `position` is stored
+	/// as in `insert_with_offset`, also for skipped batches. Returns false if
+	/// no rows were written.
+	///
+	/// # Examples
+	///
+	/// ```rust norun
+	/// let client = DBClient::new("localhost", "5432", "username", "password", "metrics");
+	/// let batch_message = BatchMessage::default();
+	/// client.insert_once(&batch_message, &[], Some(Uuid::new_v4()), None).await.unwrap();
+	/// ```
+	pub async fn insert_once(
+		&self,
+		messages: &BatchMessage,
+		routed: &[(OutOfRange, Message)],
+		batch_id: Option<Uuid>,
+		position: Option<(&str, i32, i64)>,
+	) -> Result<bool, AppError> {
+		let mut client = self.pool.get().await?;
+		let transaction = client.transaction().await?;
+
+		if let Some((topic, partition, offset)) = position {
+			// Make sure there is a row to lock, so that concurrent writers of
+			// the same partition wait for each other.
+			let stmt = transaction
+				.prepare_cached(
+					"INSERT INTO consumer_offsets (topic, partition, next_offset) VALUES ($1, $2, $3) \
+					 ON CONFLICT (topic, partition) DO NOTHING",
+				)
+				.await?;
+			transaction
+				.execute(&stmt, &[&topic, &partition, &offset])
+				.await?;
+			let stmt = transaction
+				.prepare_cached(
+					"SELECT next_offset FROM consumer_offsets WHERE topic = $1 AND partition = $2 \
+					 FOR UPDATE",
+				)
+				.await?;
+			let row = transaction.query_one(&stmt, &[&topic, &partition]).await?;
+			let next_offset: i64 = row.get(0);
+			if offset < next_offset {
+				info!(
+					"Skipping offset {} of {}/{}, it was written before",
+					offset, topic, partition
+				);
+				return Ok(false);
+			}
+		}
+
+		let mut duplicate = false;
+		if let Some(batch_id) = batch_id {
+			let stmt = transaction
+				.prepare_cached(
+					"INSERT INTO processed_batches (batch_id) VALUES ($1) \
+					 ON CONFLICT (batch_id) DO NOTHING",
+				)
+				.await?;
+			let recorded = transaction.execute(&stmt, &[&batch_id]).await?;
+			duplicate = recorded == 0;
+		}
+		if duplicate {
+			info!(
+				"Skipping batch {}, it was written before",
+				batch_id.unwrap_or_default()
+			);
+		} else {
+			self.write_rows(&transaction, messages).await?;
+			self.write_out_of_range(&transaction, routed).await?;
+		}
+
+		if let Some((topic, partition, offset)) = position {
+			let stmt = transaction
+				.prepare_cached(
+					"UPDATE consumer_offsets SET next_offset = $3 WHERE topic = $1 AND partition = $2",
+				)
+				.await?;
+			transaction
+				.execute(&stmt, &[&topic, &partition, &(offset + 1)])
+				.await?;
+		}
+		transaction.commit().await?;
+		if !duplicate {
+			info!("Published data to db");
+		}
+		Ok(!duplicate)
+	}
+
+	/// Forget the ids of batches written before `older_than` ago, returns the
+	/// number of forgotten ids.
+	///
+	/// # Examples
+	///
+	/// ```rust norun
+	/// let client = DBClient::new("localhost", "5432", "username", "password", "metrics");
+	/// client.prune_processed_batches(Duration::from_secs(86400)).await.unwrap();
+	/// ```
+	pub async fn prune_processed_batches(&self, older_than: Duration) -> Result<u64, AppError> {
+		let client = self.pool.get().await?;
+		let pruned = client
+			.execute(
+				"DELETE FROM processed_batches WHERE processed_at < now() - $1 * interval '1 second'",
+				&[&(older_than.as_secs() as f64)],
+			)
+			.await?;
+		Ok(pruned)
+	}
+
+	/// Store a batch which failed permanently in the quarantine table.
+	///
+	/// Quarantining the same kafka message again, e.g. after a redelivery,
+	/// keeps the first entry.
+	///
+	/// # Examples
+	///
+	/// ```rust norun
+	/// let client = DBClient::new("localhost", "5432", "username", "password", "metrics");
+	/// client.quarantine(&QuarantinedBatch { topic: "metrics", partition: 0, offset: 42, .. }).await.unwrap();
+	/// ```
+	pub async fn quarantine(&self, batch: &QuarantinedBatch<'_>) -> Result<(), AppError> {
+		let client = self.pool.get().await?;
 		client
-			.execute(&stmt, &[&ts, &message.name, &(message.value as f64)])
+			.execute(
+				"INSERT INTO quarantined_batches \
+				 (topic, partition, kafka_offset, content_type, payload, error, attempts) \
+				 VALUES ($1, $2, $3, $4, $5, $6, $7) \
+				 ON CONFLICT (topic, partition, kafka_offset) DO NOTHING",
+				&[
+					&batch.topic,
+					&batch.partition,
+					&batch.offset,
+					&batch.content_type,
+					&batch.payload,
+					&batch.error,
+					&(batch.attempts as i32),
+				],
+			)
 			.await?;
-		info!("Published data to db");
+		info!(
+			"Quarantined offset {} of {}/{}",
+			batch.offset, batch.topic, batch.partition
+		);
 		Ok(())
 	}
 
+	/// Get the offset from which a partition has to be consumed, if any
+	/// message of it was written with `insert_with_offset` before.
+	///
+	/// # Examples
+	///
+	/// ```rust norun
+	/// let client = DBClient::new("localhost", "5432", "username", "password", "metrics");
+	/// let offset = client.stored_offset("metrics", 0).await.unwrap();
+	/// ```
+	pub async fn stored_offset(
+		&self,
+		topic: &str,
+		partition: i32,
+	) -> Result<Option<i64>, AppError> {
+		let client = self.pool.get().await?;
+		let row = client
+			.query_opt(
+				"SELECT next_offset FROM consumer_offsets WHERE topic = $1 AND partition = $2",
+				&[&topic, &partition],
+			)
+			.await?;
+		Ok(row.map(|row| row.get(0)))
+	}
+
 	/// Truncate the table which contains all the metrics.
 	///
 	/// # Examples
@@ -194,7 +609,7 @@ impl DbClient {
 	#[allow(dead_code)]
 	pub(crate) async fn truncate(&self) -> Result<(), AppError> {
 		let client = self.pool.get().await?;
-		let stmt = client.prepare("TRUNCATE TABLE metrics").await?;
+		let stmt = client.prepare_cached("TRUNCATE TABLE metrics").await?;
 		client.execute(&stmt, &[]).await?;
 
 		info!("Published data to db");
@@ -202,10 +617,52 @@ impl DbClient {
 	}
 }
 
+/// Stream the points of `messages` into a binary COPY of the metrics table.
+/// Returns the number of written rows.
+async fn copy_rows(sink: CopyInSink<Bytes>, messages: &BatchMessage) -> Result<u64, AppError> {
+	let writer = BinaryCopyInWriter::new(
+		sink,
+		&[Type::TIMESTAMPTZ, Type::TEXT, Type::FLOAT8, Type::JSONB],
+	);
+	tokio::pin!(writer);
+	for message in messages.multiple_points.iter() {
+		let ts = point_time(message)?;
+		writer
+			.as_mut()
+			.write(&[
+				&ts,
+				&message.name,
+				&(message.value as f64),
+				&Json(&message.labels),
+			])
+			.await?;
+	}
+	Ok(writer.finish().await?)
+}
+
 #[cfg(test)]
 mod tests {
 	use super::*;
-	use crate::MetricsGenerator;
+	use crate::metrics::MetricsGenerator;
+
+	#[test]
+	fn test_columns_convert_milliseconds() {
+		let message =
+			MetricsGenerator::create_metrics("user".to_string(), 1f32, Some(1_666_000_000_123));
+		let mut columns = Columns::default();
+		columns.push(&message).unwrap();
+		assert_eq!(
+			columns.timestamps,
+			vec![Utc.timestamp_millis_opt(1_666_000_000_123).unwrap()]
+		);
+		assert_eq!(columns.timestamps[0].timestamp_subsec_millis(), 123);
+
+		let message = MetricsGenerator::create_metrics("user".to_string(), 1f32, Some(i64::MAX));
+		let err = columns.push(&message).unwrap_err();
+		assert!(matches!(err, AppError::Timestamp(i64::MAX)));
+		assert!(!err.is_transient());
+	}
+
 	#[tokio::test]
 	async fn test_insert_single_message() {
 		let client = DbClient::new("localhost", "5432", "postgres", "password", "timeseries");
@@ -252,4 +709,201 @@ mod tests {
 			actual
 		);
 	}
+
+	#[tokio::test]
+	async fn test_insert_failing_batch_writes_nothing() {
+		let client = DbClient::new("localhost", "5432", "postgres", "password", "timeseries");
+
+		// Clean up the DB first
+		client.truncate().await.unwrap();
+
+		// Both points have the same name, labels and timestamp, so the second
+		// one conflicts.
+		let message1 = MetricsGenerator::create_metrics("user".to_string(), 321f32, Some(1));
+		let message2 = MetricsGenerator::create_metrics("user".to_string(), 123f32, Some(1));
+		let batch_message = BatchMessage {
+			multiple_points: vec![message1, message2],
+		};
+		assert!(client.insert(&batch_message).await.is_err());
+
+		assert_eq!(client.get_count().await.unwrap(), 0);
+	}
+
+	#[tokio::test]
+	async fn test_insert_batch_message_with_copy() {
+		let client = DbClient::new("localhost", "5432", "postgres", "password", "timeseries")
+			.with_copy_threshold(2);
+
+		// Clean up the DB first
+		client.truncate().await.unwrap();
+
+		let message1 = MetricsGenerator::create_metrics("user1".to_string(), 321f32, Some(1));
+		let message2 = MetricsGenerator::create_metrics("user2".to_string(), 321f32, Some(2));
+		let batch_message = BatchMessage {
+			multiple_points: vec![message1, message2],
+		};
+		assert!(client.uses_copy(&batch_message));
+		client.insert(&batch_message).await.unwrap();
+
+		assert_eq!(client.get_count().await.unwrap(), 2);
+	}
+
+	#[tokio::test]
+	async fn test_insert_with_conflict_policy() {
+		let client = DbClient::new("localhost", "5432", "postgres", "password", "timeseries");
+
+		// Clean up the DB first
+		client.truncate().await.unwrap();
+
+		// Points with the same timestamp but another name don't conflict.
+		let other = MetricsGenerator::create_metrics("other".to_string(), 0f32, Some(1));
+		client.insert_message(&other).await.unwrap();
+		let batch = |values: &[f32]| BatchMessage {
+			multiple_points: values
+				.iter()
+				.map(|value| MetricsGenerator::create_metrics("user".to_string(), *value, Some(1)))
+				.collect(),
+		};
+		let value = |client: DbClient| async move {
+			let pool_client = client.pool.get().await.unwrap();
+			let row = pool_client
+				.query_one("SELECT value FROM metrics WHERE name = 'user'", &[])
+				.await
+				.unwrap();
+			row.get::<_, f64>(0)
+		};
+		client.insert(&batch(&[1.0])).await.unwrap();
+		assert!(client.insert(&batch(&[2.0])).await.is_err());
+
+		let client = client.with_conflict_policy(ConflictPolicy::Nothing);
+		client.insert(&batch(&[2.0])).await.unwrap();
+		assert_eq!(value(client.clone()).await, 1.0);
+
+		let client = client.with_conflict_policy(ConflictPolicy::Update);
+		client.insert(&batch(&[2.0, 3.0])).await.unwrap();
+		assert_eq!(value(client.clone()).await, 3.0);
+
+		// COPY goes through the staging table.
+		let client = client.with_copy_threshold(1);
+		client.insert(&batch(&[4.0, 5.0])).await.unwrap();
+		assert_eq!(value(client.clone()).await, 5.0);
+
+		assert_eq!(client.get_count().await.unwrap(), 2);
+	}
+
+	#[tokio::test]
+	async fn test_insert_with_offset_skips_redelivery() {
+		let client = DbClient::new("localhost", "5432", "postgres", "password", "timeseries");
+
+		// Clean up the DB first
+		client.truncate().await.unwrap();
+		let topic = uuid::Uuid::new_v4().to_string();
+
+		let message = MetricsGenerator::create_metrics("user".to_string(), 321f32, None);
+		let batch_message = BatchMessage {
+			multiple_points: vec![message],
+		};
+
+		assert!(client
+			.insert_with_offset(&batch_message, &topic, 0, 7)
+			.await
+			.unwrap());
+		// A redelivery of the same offset doesn't create another row.
+		assert!(!client
+			.insert_with_offset(&batch_message, &topic, 0, 7)
+			.await
+			.unwrap());
+
+		assert_eq!(client.get_count().await.unwrap(), 1);
+		assert_eq!(client.stored_offset(&topic, 0).await.unwrap(), Some(8));
+		assert_eq!(client.stored_offset(&topic, 1).await.unwrap(), None);
+	}
+
+	#[tokio::test]
+	async fn test_insert_once_skips_written_batch() {
+		let client = DbClient::new("localhost", "5432", "postgres", "password", "timeseries");
+
+		// Clean up the DB first
+		client.truncate().await.unwrap();
+		let topic = uuid::Uuid::new_v4().to_string();
+		let batch_id = Uuid::new_v4();
+
+		let message = MetricsGenerator::create_metrics("user".to_string(), 321f32, None);
+		let batch_message = BatchMessage {
+			multiple_points: vec![message],
+		};
+		let late = MetricsGenerator::create_metrics(topic.clone(), 123f32, Some(0));
+		let routed = [(OutOfRange::Late, late)];
+
+		assert!(client
+			.insert_once(
+				&batch_message,
+				&routed,
+				Some(batch_id),
+				Some((&topic, 0, 7))
+			)
+			.await
+			.unwrap());
+		// A producer retry ends up at another offset, which is still stored.
+		assert!(!client
+			.insert_once(
+				&batch_message,
+				&routed,
+				Some(batch_id),
+				Some((&topic, 0, 8))
+			)
+			.await
+			.unwrap());
+		// A redelivery of the first offset is skipped as well.
+		assert!(!client
+			.insert_once(&batch_message, &routed, None, Some((&topic, 0, 7)))
+			.await
+			.unwrap());
+
+		assert_eq!(client.get_count().await.unwrap(), 1);
+		assert_eq!(client.stored_offset(&topic, 0).await.unwrap(), Some(9));
+		let routed_rows: i64 = client
+			.pool
+			.get()
+			.await
+			.unwrap()
+			.query_one(
+				"SELECT COUNT(*) FROM out_of_range_metrics WHERE name = $1",
+				&[&topic],
+			)
+			.await
+			.unwrap()
+			.get(0);
+		assert_eq!(routed_rows, 1);
+	}
+
+	#[tokio::test]
+	async fn test_quarantine_keeps_first_entry() {
+		let client = DbClient::new("localhost", "5432", "postgres", "password", "timeseries");
+		let topic = uuid::Uuid::new_v4().to_string();
+		let mut batch = QuarantinedBatch {
+			topic: &topic,
+			partition: 0,
+			offset: 7,
+			content_type: "application/x-protobuf",
+			payload: b"payload",
+			error: "duplicate key value".to_string(),
+			attempts: 1,
+		};
+		client.quarantine(&batch).await.unwrap();
+
+		// A redelivery of the same offset is quarantined once.
+		batch.attempts = 2;
+		client.quarantine(&batch).await.unwrap();
+		let pool_client = client.pool.get().await.unwrap();
+		let row = pool_client
+			.query_one(
+				"SELECT count(*), max(attempts) FROM quarantined_batches WHERE topic = $1",
+				&[&topic],
+			)
+			.await
+			.unwrap();
+		assert_eq!(row.get::<_, i64>(0), 1);
+		assert_eq!(row.get::<_, i32>(1), 1);
+	}
 }
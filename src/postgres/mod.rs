@@ -1,2 +1,2 @@
 mod client;
-pub use client::DbClient;
+pub use client::{ConflictPolicy, DbClient, QuarantinedBatch, DEFAULT_COPY_THRESHOLD};
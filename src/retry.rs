@@ -0,0 +1,154 @@
+// MIT License
+//
+// Copyright (c) 2019 Ankur Srivastava
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use crate::errors::AppError;
+use log::warn;
+use std::future::Future;
+use tokio::time::{self, Duration};
+
+/// How often and how fast a failing operation is retried.
+///
+/// Only transient errors are retried, see `AppError::is_transient`. The
+/// backoff grows exponentially, with full jitter so that the workers don't
+/// hit a recovering database at the same time.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RetryPolicy {
+	/// Number of attempts in total, including the first one.
+	pub max_attempts: u32,
+	/// Upper bound of the backoff after the first attempt.
+	pub initial_backoff: Duration,
+	/// Upper bound of every backoff.
+	pub max_backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+	fn default() -> Self {
+		RetryPolicy {
+			max_attempts: 5,
+			initial_backoff: Duration::from_millis(100),
+			max_backoff: Duration::from_secs(5),
+		}
+	}
+}
+
+impl RetryPolicy {
+	/// Run `operation` until it succeeds, fails with a permanent error or
+	/// used up every attempt. On failure, returns the last error along with
+	/// the number of attempts made.
+	///
+	/// # Examples
+	/// Basic usage:
+	///
+	/// ```rust norun
+	/// let policy = RetryPolicy::default();
+	/// policy.run(|| dbclient.insert(&batch_message)).await?;
+	/// ```
+	pub async fn run<T, F, Fut>(&self, mut operation: F) -> Result<T, (AppError, u32)>
+	where
+		F: FnMut() -> Fut,
+		Fut: Future<Output = Result<T, AppError>>,
+	{
+		let mut attempts = 0;
+		loop {
+			attempts += 1;
+			match operation().await {
+				Ok(value) => return Ok(value),
+				Err(e) if !e.is_transient() || attempts >= self.max_attempts => {
+					return Err((e, attempts))
+				}
+				Err(e) => {
+					let backoff = self.backoff(attempts);
+					warn!(
+						"Attempt {} failed, retrying in {:?}: {}",
+						attempts, backoff, e
+					);
+					time::sleep(backoff).await;
+				}
+			}
+		}
+	}
+
+	/// Random backoff after `attempts` failed attempts.
+	fn backoff(&self, attempts: u32) -> Duration {
+		let ceiling = self
+			.initial_backoff
+			.checked_mul(2u32.saturating_pow(attempts.saturating_sub(1)))
+			.unwrap_or(self.max_backoff)
+			.min(self.max_backoff);
+		Duration::from_millis(rand::random_range(0..=ceiling.as_millis() as u64))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::io;
+
+	fn policy() -> RetryPolicy {
+		RetryPolicy {
+			max_attempts: 3,
+			initial_backoff: Duration::from_millis(1),
+			max_backoff: Duration::from_millis(2),
+		}
+	}
+
+	#[test]
+	fn test_backoff_is_bounded() {
+		let policy = RetryPolicy::default();
+		for attempts in 1..100 {
+			assert!(policy.backoff(attempts) <= policy.max_backoff);
+		}
+		assert!(policy.backoff(1) <= policy.initial_backoff);
+	}
+
+	#[tokio::test]
+	async fn test_transient_errors_are_retried() {
+		let mut calls = 0;
+		let result = policy()
+			.run(|| {
+				calls += 1;
+				let result = if calls < 3 {
+					Err(AppError::Io(io::ErrorKind::ConnectionReset.into()))
+				} else {
+					Ok(calls)
+				};
+				async move { result }
+			})
+			.await;
+		assert_eq!(result.unwrap(), 3);
+
+		let result: Result<(), _> = policy()
+			.run(|| async { Err(AppError::Io(io::ErrorKind::ConnectionReset.into())) })
+			.await;
+		assert_eq!(result.unwrap_err().1, 3);
+	}
+
+	#[tokio::test]
+	async fn test_permanent_errors_are_not_retried() {
+		let result: Result<(), _> = policy()
+			.run(|| async { Err(AppError::LineProtocol("bad line".into())) })
+			.await;
+		let (e, attempts) = result.unwrap_err();
+		assert!(!e.is_transient());
+		assert_eq!(attempts, 1);
+	}
+}
@@ -0,0 +1,156 @@
+// MIT License
+//
+// Copyright (c) 2019 Ankur Srivastava
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use log::{info, warn};
+use std::{future::Future, sync::Arc};
+use tokio::{
+	signal,
+	sync::watch,
+	task,
+	time::{self, Duration},
+};
+
+/// Tells the tasks of the application that they have to stop, once the
+/// process received SIGINT or SIGTERM, or a task requested it.
+///
+/// Clones share the same signal, so every task can keep its own handle.
+#[derive(Clone, Debug)]
+pub struct Shutdown {
+	requested: watch::Receiver<bool>,
+	trigger: Arc<watch::Sender<bool>>,
+}
+
+impl Shutdown {
+	/// Start listening for SIGINT and SIGTERM.
+	///
+	/// # Examples
+	/// Basic usage:
+	///
+	/// ```rust norun
+	/// let shutdown = Shutdown::listen();
+	/// shutdown.with_deadline(run(shutdown.clone()), Duration::from_secs(30)).await;
+	/// ```
+	pub fn listen() -> Shutdown {
+		let shutdown = Shutdown::new();
+		let trigger = shutdown.clone();
+		task::spawn(async move {
+			wait_for_signal().await;
+			info!("Shutting down, send the signal again to exit immediately");
+			trigger.request();
+			wait_for_signal().await;
+			warn!("Exiting immediately");
+			std::process::exit(1);
+		});
+		shutdown
+	}
+
+	/// Create a shutdown which is only requested through `request`.
+	pub fn new() -> Shutdown {
+		let (trigger, requested) = watch::channel(false);
+		Shutdown {
+			requested,
+			trigger: Arc::new(trigger),
+		}
+	}
+
+	/// Tell every task to stop.
+	pub fn request(&self) {
+		let _ = self.trigger.send(true);
+	}
+
+	/// Check whether the application has to stop.
+	pub fn is_requested(&self) -> bool {
+		*self.requested.borrow()
+	}
+
+	/// Wait until the application has to stop.
+	pub async fn requested(&mut self) {
+		while !*self.requested.borrow() {
+			// The sender lives as long as any handle, so this can't fail.
+			let _ = self.requested.changed().await;
+		}
+	}
+
+	/// Run `future` to completion, but give up on it once it didn't complete
+	/// within `deadline` after the shutdown was requested. Returns None in
+	/// that case.
+	pub async fn with_deadline<F: Future>(
+		&self,
+		future: F,
+		deadline: Duration,
+	) -> Option<F::Output> {
+		tokio::pin!(future);
+		let mut shutdown = self.clone();
+		tokio::select! {
+			output = &mut future => return Some(output),
+			_ = shutdown.requested() => {}
+		}
+		match time::timeout(deadline, future).await {
+			Ok(output) => Some(output),
+			Err(_) => {
+				warn!("Didn't shut down within {:?}, giving up", deadline);
+				None
+			}
+		}
+	}
+}
+
+impl Default for Shutdown {
+	fn default() -> Self {
+		Shutdown::new()
+	}
+}
+
+/// Wait for the next SIGINT or SIGTERM.
+async fn wait_for_signal() {
+	#[cfg(unix)]
+	{
+		let mut terminate = signal::unix::signal(signal::unix::SignalKind::terminate())
+			.expect("Failed to listen for SIGTERM");
+		tokio::select! {
+			_ = signal::ctrl_c() => {}
+			_ = terminate.recv() => {}
+		}
+	}
+	#[cfg(not(unix))]
+	signal::ctrl_c().await.expect("Failed to listen for SIGINT");
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::future;
+
+	#[tokio::test]
+	async fn test_shutdown_deadline() {
+		let shutdown = Shutdown::new();
+		assert!(!shutdown.is_requested());
+		let finished = shutdown.with_deadline(async { 1 }, Duration::from_millis(10));
+		assert_eq!(finished.await, Some(1));
+
+		shutdown.request();
+		assert!(shutdown.is_requested());
+		shutdown.clone().requested().await;
+		let stuck = shutdown.with_deadline(future::pending::<()>(), Duration::from_millis(10));
+		assert_eq!(stuck.await, None);
+	}
+}
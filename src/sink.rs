@@ -0,0 +1,443 @@
+// MIT License
+//
+// Copyright (c) 2019 Ankur Srivastava
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use crate::{
+	codec::{Codec, CONTENT_TYPE_HEADER},
+	dedup::{batch_id, DedupWindow},
+	errors::AppError,
+	generated::{BatchMessage, Message},
+	kafka::{decrypt_payload, Acknowledger, DeadLetterQueue, KafkaMessage, Keyring},
+	metrics::Registry,
+	pipeline::Pipeline,
+	postgres::{DbClient, QuarantinedBatch},
+	retry::RetryPolicy,
+	shutdown::Shutdown,
+	timeliness::{OutOfRange, Timeliness},
+};
+use chrono::Utc;
+use futures::FutureExt;
+use log::{debug, error, info};
+use std::{any::Any, panic::AssertUnwindSafe, sync::Arc};
+use tokio::{
+	sync::{mpsc, Mutex},
+	time,
+};
+use uuid::Uuid;
+
+/// Number of messages a worker panicked on.
+pub const PANICS_METRIC: &str = "sink-panics";
+
+/// Writes the messages consumed from kafka to postgres.
+///
+/// Every message is acknowledged once it is done with: written, or handed to
+/// the dead-letter topic. A sink is shared by all the workers of a
+/// subscriber.
+pub struct Sink {
+	dbclient: DbClient,
+	acks: Acknowledger,
+	default_codec: Codec,
+	keyring: Option<Keyring>,
+	dead_letters: Option<DeadLetterQueue>,
+	pipeline: Pipeline,
+	timeliness: Timeliness,
+	metrics: Arc<Registry>,
+	retry: RetryPolicy,
+	dedup: std::sync::Mutex<DedupWindow>,
+	dedup_table: bool,
+	exactly_once: bool,
+	shutdown: Shutdown,
+}
+
+impl Sink {
+	/// Create a Sink which decodes messages without a content-type header
+	/// with `default_codec`.
+	///
+	/// # Examples
+	/// Basic usage:
+	///
+	/// ```rust norun
+	/// let sink = Sink::new(dbclient, kconsumer.acknowledger(), Codec::Protobuf);
+	/// ```
+	pub fn new(dbclient: DbClient, acks: Acknowledger, default_codec: Codec) -> Sink {
+		Sink {
+			dbclient,
+			acks,
+			default_codec,
+			keyring: None,
+			dead_letters: None,
+			pipeline: Pipeline::default(),
+			timeliness: Timeliness::default(),
+			metrics: Arc::new(Registry::default()),
+			retry: RetryPolicy::default(),
+			dedup: std::sync::Mutex::new(DedupWindow::default()),
+			dedup_table: false,
+			exactly_once: false,
+			shutdown: Shutdown::new(),
+		}
+	}
+
+	/// Decrypt encrypted messages with the keys of this keyring.
+	pub fn with_keyring(mut self, keyring: Keyring) -> Sink {
+		self.keyring = Some(keyring);
+		self
+	}
+
+	/// Transform and filter every batch with this pipeline before writing it.
+	pub fn with_pipeline(mut self, pipeline: Pipeline) -> Sink {
+		self.pipeline = pipeline;
+		self
+	}
+
+	/// Handle points outside of the accepted time range according to these
+	/// policies.
+	pub fn with_timeliness(mut self, timeliness: Timeliness) -> Sink {
+		self.timeliness = timeliness;
+		self
+	}
+
+	/// Report the metrics of the sink to this registry.
+	pub fn with_metrics(mut self, metrics: Arc<Registry>) -> Sink {
+		self.metrics = metrics;
+		self
+	}
+
+	/// Retry writes failing with transient errors according to this policy.
+	pub fn with_retry(mut self, retry: RetryPolicy) -> Sink {
+		self.retry = retry;
+		self
+	}
+
+	/// Publish messages which can't be handled to a dead-letter topic.
+	pub fn with_dead_letters(mut self, dead_letters: DeadLetterQueue) -> Sink {
+		self.dead_letters = Some(dead_letters);
+		self
+	}
+
+	/// Skip batches whose batch-id header matches one of the last
+	/// `window_size` written batches. With `table`, the ids are recorded in
+	/// postgres along with the rows as well, which catches duplicates across
+	/// restarts.
+	pub fn with_dedup(mut self, window_size: usize, table: bool) -> Sink {
+		self.dedup = std::sync::Mutex::new(DedupWindow::new(window_size));
+		self.dedup_table = table;
+		self
+	}
+
+	/// Store the offset of each message along with its rows.
+	pub fn with_exactly_once(mut self, exactly_once: bool) -> Sink {
+		self.exactly_once = exactly_once;
+		self
+	}
+
+	/// Stop waiting for the db to write a batch once the shutdown is
+	/// requested.
+	pub fn with_shutdown(mut self, shutdown: Shutdown) -> Sink {
+		self.shutdown = shutdown;
+		self
+	}
+
+	/// Handle the messages of one worker lane, one after the other.
+	///
+	/// The queue is shared so that a restarted worker continues with it.
+	pub async fn run(self: Arc<Self>, worker: usize, rx: Arc<Mutex<mpsc::Receiver<KafkaMessage>>>) {
+		info!(
+			"Worker {} is waiting to receive metrics-data on incoming queue.",
+			worker
+		);
+		let mut rx = rx.lock().await;
+		while let Some(kmessage) = rx.recv().await {
+			debug!("Received data on the incoming channel to write in database");
+			// A panic would lose the message and hold back the commits of its
+			// partition, so the message goes to the dead-letter topic instead.
+			let panic = match AssertUnwindSafe(self.handle(&kmessage))
+				.catch_unwind()
+				.await
+			{
+				Ok(true) => continue,
+				Ok(false) => {
+					info!("Worker {} stops with a batch left unwritten", worker);
+					break;
+				}
+				Err(panic) => panic,
+			};
+			let reason = format!("Panicked: {}", panic_message(&*panic));
+			error!(
+				"Worker {} failed on offset {} of {}/{}: {}",
+				worker, kmessage.offset, kmessage.topic, kmessage.partition, reason
+			);
+			self.metrics.increment(PANICS_METRIC, &[], 1.0);
+			if self.dead_letter(&kmessage, reason, 1).await || self.dead_letters.is_none() {
+				self.acks.ack(&kmessage.ack());
+			}
+		}
+	}
+
+	/// Decrypt, decode and write a single message.
+	///
+	/// Returns false if the message was left unwritten because the shutdown
+	/// was requested. The lane has to stop then, so that the later messages
+	/// of its partition aren't written around it.
+	pub async fn handle(&self, kmessage: &KafkaMessage) -> bool {
+		// Messages which can't be decrypted or decoded won't get any better
+		// on redelivery, so they are acknowledged as well.
+		let payload = match decrypt_payload(self.keyring.as_ref(), kmessage) {
+			Ok(payload) => payload,
+			Err(e) => {
+				error!("Failed to decrypt the incoming message from kafka: {:?}", e);
+				let reason = format!("Failed to decrypt: {}", e);
+				if self.dead_letter(kmessage, reason, 1).await || self.dead_letters.is_none() {
+					self.acks.ack(&kmessage.ack());
+				}
+				return true;
+			}
+		};
+		let codec = kmessage
+			.header(CONTENT_TYPE_HEADER)
+			.and_then(Codec::from_content_type)
+			.unwrap_or(self.default_codec);
+		let mut bmsg = match codec.decode(&payload) {
+			Ok(bmsg) => bmsg,
+			Err(e) => {
+				error!("Failed to decode the incoming message from kafka: {:?}", e);
+				let reason = format!("Failed to decode: {}", e);
+				if self.dead_letter(kmessage, reason, 1).await || self.dead_letters.is_none() {
+					self.acks.ack(&kmessage.ack());
+				}
+				return true;
+			}
+		};
+
+		// Batches which end up empty are still written, so that exactly-once
+		// mode stores their offset.
+		self.pipeline.apply(&mut bmsg);
+		let routed = self
+			.timeliness
+			.apply(&mut bmsg, Utc::now().timestamp_millis(), &self.metrics);
+
+		let batch_id = batch_id(kmessage);
+		if let Some(batch_id) = &batch_id {
+			if self.dedup.lock().unwrap().contains(batch_id) {
+				info!(
+					"Skipping batch {} at offset {} of {}/{}, it was written before",
+					batch_id, kmessage.offset, kmessage.topic, kmessage.partition
+				);
+				self.acks.ack(&kmessage.ack());
+				return true;
+			}
+		}
+
+		let write = || {
+			self.retry
+				.run(|| self.insert(&bmsg, &routed, kmessage, batch_id))
+		};
+		let mut result = write().await;
+		loop {
+			let (e, attempts) = match result {
+				Ok(()) => {
+					if let Some(batch_id) = batch_id {
+						self.dedup.lock().unwrap().insert(batch_id);
+					}
+					self.acks.ack(&kmessage.ack());
+					return true;
+				}
+				Err((e, attempts)) if e.is_transient() => (e, attempts),
+				Err((e, attempts)) => {
+					self.set_aside(kmessage, codec, &payload, e, attempts).await;
+					return true;
+				}
+			};
+			// The database is unavailable, the batch itself may be fine.
+			error!(
+				"Failed to write offset {} of {}/{} to the db after {} attempts: {:?}",
+				kmessage.offset, kmessage.topic, kmessage.partition, attempts, e
+			);
+			let reason = format!("Failed to write to the db: {}", e);
+			if self.dead_letter(kmessage, reason, attempts).await {
+				self.acks.ack(&kmessage.ack());
+				return true;
+			}
+			// Moving on would leave the offset in flight for good, which holds
+			// back the commits of the partition while the later messages get
+			// written around it. So the lane waits for the db instead.
+			let mut shutdown = self.shutdown.clone();
+			result = tokio::select! {
+				result = async {
+					time::sleep(self.retry.max_backoff).await;
+					write().await
+				} => result,
+				_ = shutdown.requested() => {
+					info!(
+						"Leaving offset {} of {}/{} unwritten on shutdown",
+						kmessage.offset, kmessage.topic, kmessage.partition
+					);
+					return false;
+				}
+			};
+		}
+	}
+
+	/// Keep a batch which will never be written aside, in the quarantine table
+	/// or the dead-letter topic, so that it doesn't hold back its partition.
+	async fn set_aside(
+		&self,
+		kmessage: &KafkaMessage,
+		codec: Codec,
+		payload: &[u8],
+		e: AppError,
+		attempts: u32,
+	) {
+		error!(
+			"Failed to write offset {} of {}/{} to the db, quarantining it: {:?}",
+			kmessage.offset, kmessage.topic, kmessage.partition, e
+		);
+		let batch = QuarantinedBatch {
+			topic: &kmessage.topic,
+			partition: kmessage.partition,
+			offset: kmessage.offset,
+			content_type: codec.content_type(),
+			payload,
+			error: e.to_string(),
+			attempts,
+		};
+		let reason = format!("Failed to write to the db: {}", e);
+		if self.quarantine(&batch).await || self.dead_letter(kmessage, reason, attempts).await {
+			self.acks.ack(&kmessage.ack());
+		}
+	}
+
+	/// Store a batch in the quarantine table. Returns whether it was stored.
+	async fn quarantine(&self, batch: &QuarantinedBatch<'_>) -> bool {
+		match self.dbclient.quarantine(batch).await {
+			Ok(()) => true,
+			Err(e) => {
+				error!("Failed to quarantine the batch: {:?}", e);
+				false
+			}
+		}
+	}
+
+	async fn insert(
+		&self,
+		bmsg: &BatchMessage,
+		routed: &[(OutOfRange, Message)],
+		kmessage: &KafkaMessage,
+		batch_id: Option<Uuid>,
+	) -> Result<(), AppError> {
+		let batch_id = batch_id.filter(|_| self.dedup_table);
+		let position = if self.exactly_once {
+			Some((kmessage.topic.as_str(), kmessage.partition, kmessage.offset))
+		} else {
+			None
+		};
+		if routed.is_empty() && batch_id.is_none() && position.is_none() {
+			return self.dbclient.insert(bmsg).await;
+		}
+		self.dbclient
+			.insert_once(bmsg, routed, batch_id, position)
+			.await
+			.map(|_| ())
+	}
+
+	/// Hand a message which couldn't be handled to the dead-letter topic.
+	///
+	/// Returns true if it was published there, the message is then done with.
+	async fn dead_letter(&self, kmessage: &KafkaMessage, reason: String, attempts: u32) -> bool {
+		let dead_letters = match &self.dead_letters {
+			Some(dead_letters) => dead_letters,
+			None => return false,
+		};
+		match dead_letters.send(kmessage, &reason, attempts).await {
+			Ok(()) => true,
+			Err(e) => {
+				error!(
+					"Failed to dead-letter offset {} of {}/{}: {:?}",
+					kmessage.offset, kmessage.topic, kmessage.partition, e
+				);
+				false
+			}
+		}
+	}
+}
+
+/// Get the message a panic was raised with.
+fn panic_message(panic: &(dyn Any + Send)) -> &str {
+	if let Some(message) = panic.downcast_ref::<&str>() {
+		message
+	} else if let Some(message) = panic.downcast_ref::<String>() {
+		message
+	} else {
+		"unknown panic"
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::kafka::SubscriberContext;
+	use tokio::{task, time::Duration};
+
+	#[tokio::test]
+	async fn test_unavailable_db_without_dead_letters_blocks_the_lane() {
+		// Nothing listens on port 1, so every write fails with a transient
+		// error.
+		let dbclient = DbClient::new("localhost", "1", "postgres", "password", "timeseries");
+		let context = Arc::new(SubscriberContext::default());
+		let shutdown = Shutdown::new();
+		let sink = Sink::new(
+			dbclient,
+			Acknowledger::new(context.clone()),
+			Codec::Protobuf,
+		)
+		.with_retry(RetryPolicy {
+			max_attempts: 2,
+			initial_backoff: Duration::from_millis(1),
+			max_backoff: Duration::from_millis(5),
+		})
+		.with_shutdown(shutdown.clone());
+		let sink = Arc::new(sink);
+		let kmessage = KafkaMessage {
+			topic: "metrics".to_string(),
+			partition: 0,
+			offset: 7,
+			..Default::default()
+		};
+		context.offsets().dispatch("metrics", 0, 7);
+		let partitions = [("metrics".to_string(), 0)];
+
+		let handling = task::spawn({
+			let sink = sink.clone();
+			async move { sink.handle(&kmessage).await }
+		});
+		time::sleep(Duration::from_millis(200)).await;
+		// The batch is neither skipped nor acknowledged while the db is down.
+		assert!(!handling.is_finished());
+		assert!(context.offsets().has_in_flight(&partitions));
+
+		shutdown.request();
+		let handled = time::timeout(Duration::from_secs(5), handling)
+			.await
+			.unwrap()
+			.unwrap();
+		assert!(!handled);
+		assert!(context.offsets().has_in_flight(&partitions));
+	}
+}
@@ -0,0 +1,228 @@
+// MIT License
+//
+// Copyright (c) 2019 Ankur Srivastava
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use crate::{errors::AppError, metrics::Registry, shutdown::Shutdown};
+use log::{error, info, warn};
+use serde::Deserialize;
+use std::{
+	future::Future,
+	sync::{Arc, Mutex},
+};
+use tokio::{
+	task::{self, JoinHandle},
+	time::{self, Duration, Instant},
+};
+
+/// Number of times a task was restarted after it panicked, labelled with the
+/// name of the task.
+pub const TASK_RESTARTS_METRIC: &str = "supervisor-task-restarts";
+
+/// Backoff before the first restart of a task.
+const MIN_BACKOFF: Duration = Duration::from_secs(1);
+
+/// What the supervisor does when one of its tasks panics.
+#[derive(Deserialize, Debug, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum FailurePolicy {
+	/// Start the task again after a backoff, which doubles with every restart
+	/// in a row.
+	#[default]
+	Restart,
+
+	/// Shut the whole process down, which then exits with an error.
+	Fail,
+}
+
+/// Watches the tasks of a pipeline, so that a panicking task doesn't leave
+/// the process running without doing anything.
+#[derive(Clone)]
+pub struct Supervisor {
+	policy: FailurePolicy,
+	max_backoff: Duration,
+	metrics: Arc<Registry>,
+	shutdown: Shutdown,
+	failed: Arc<Mutex<Option<String>>>,
+}
+
+impl Supervisor {
+	/// Create a new Supervisor, which reports restarts to `metrics` and
+	/// requests `shutdown` when a task fails for good.
+	///
+	/// # Examples
+	/// Basic usage:
+	///
+	/// ```rust norun
+	/// let supervisor = Supervisor::new(FailurePolicy::Restart, Duration::from_secs(60), metrics, shutdown);
+	/// supervisor.spawn("collector", move || collect(tx.clone()));
+	/// ```
+	pub fn new(
+		policy: FailurePolicy,
+		max_backoff: Duration,
+		metrics: Arc<Registry>,
+		shutdown: Shutdown,
+	) -> Supervisor {
+		Supervisor {
+			policy,
+			max_backoff,
+			metrics,
+			shutdown,
+			failed: Arc::new(Mutex::new(None)),
+		}
+	}
+
+	/// Run the future created by `start` as a task, and start it again if it
+	/// panics, according to the policy. The returned handle completes once
+	/// the task returned, or failed for good.
+	pub fn spawn<F, Fut>(&self, name: &str, mut start: F) -> JoinHandle<()>
+	where
+		F: FnMut() -> Fut + Send + 'static,
+		Fut: Future<Output = ()> + Send + 'static,
+	{
+		let supervisor = self.clone();
+		let name = name.to_string();
+		task::spawn(async move {
+			let mut restarts = 0;
+			loop {
+				let started = Instant::now();
+				let e = match task::spawn(start()).await {
+					Ok(()) => return,
+					Err(e) if e.is_panic() => e,
+					Err(_) => return,
+				};
+				error!("Task {} panicked: {}", name, e);
+				if supervisor.policy == FailurePolicy::Fail {
+					supervisor.fail(&name);
+					return;
+				}
+
+				// A task which ran for a while is not failing in a loop.
+				if started.elapsed() >= supervisor.max_backoff {
+					restarts = 0;
+				}
+				let backoff = backoff(restarts, supervisor.max_backoff);
+				restarts += 1;
+				warn!("Restarting task {} in {:?}", name, backoff);
+				let mut shutdown = supervisor.shutdown.clone();
+				tokio::select! {
+					_ = time::sleep(backoff) => {}
+					_ = shutdown.requested() => return,
+				}
+				supervisor
+					.metrics
+					.increment(TASK_RESTARTS_METRIC, &[("task", &name)], 1.0);
+			}
+		})
+	}
+
+	/// Returns an error naming the task which failed, if any did.
+	pub fn result(&self) -> Result<(), AppError> {
+		match self.failed.lock().unwrap().clone() {
+			Some(name) => Err(AppError::TaskFailed(name)),
+			None => Ok(()),
+		}
+	}
+
+	/// Remember that `name` failed and stop the other tasks.
+	fn fail(&self, name: &str) {
+		info!("Task {} failed, shutting down", name);
+		self.failed
+			.lock()
+			.unwrap()
+			.get_or_insert_with(|| name.to_string());
+		self.shutdown.request();
+	}
+}
+
+/// Backoff before the restart which follows `restarts` restarts in a row.
+fn backoff(restarts: u32, max_backoff: Duration) -> Duration {
+	MIN_BACKOFF
+		.checked_mul(2u32.saturating_pow(restarts))
+		.unwrap_or(max_backoff)
+		.min(max_backoff)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::sync::atomic::{AtomicUsize, Ordering};
+
+	#[test]
+	fn test_backoff() {
+		let max = Duration::from_secs(60);
+		assert_eq!(backoff(0, max), Duration::from_secs(1));
+		assert_eq!(backoff(3, max), Duration::from_secs(8));
+		assert_eq!(backoff(6, max), max);
+		assert_eq!(backoff(100, max), max);
+	}
+
+	#[tokio::test]
+	async fn test_failing_task_shuts_down() {
+		let shutdown = Shutdown::new();
+		let metrics = Arc::new(Registry::default());
+		let supervisor =
+			Supervisor::new(FailurePolicy::Fail, MIN_BACKOFF, metrics, shutdown.clone());
+		let starts = Arc::new(AtomicUsize::new(0));
+		let counter = starts.clone();
+		supervisor
+			.spawn("panicking", move || {
+				counter.fetch_add(1, Ordering::SeqCst);
+				async { panic!("boom") }
+			})
+			.await
+			.unwrap();
+
+		assert_eq!(starts.load(Ordering::SeqCst), 1);
+		assert!(shutdown.is_requested());
+		assert!(supervisor.result().is_err());
+	}
+
+	#[tokio::test]
+	async fn test_panicking_task_is_restarted() {
+		let metrics = Arc::new(Registry::default());
+		let supervisor = Supervisor::new(
+			FailurePolicy::Restart,
+			Duration::from_millis(10),
+			metrics.clone(),
+			Shutdown::new(),
+		);
+		let starts = Arc::new(AtomicUsize::new(0));
+		let counter = starts.clone();
+		supervisor
+			.spawn("flaky", move || {
+				let start = counter.fetch_add(1, Ordering::SeqCst);
+				async move {
+					if start < 2 {
+						panic!("boom");
+					}
+				}
+			})
+			.await
+			.unwrap();
+
+		assert_eq!(starts.load(Ordering::SeqCst), 3);
+		assert_eq!(
+			metrics.get(TASK_RESTARTS_METRIC, &[("task", "flaky")]),
+			Some(2.0)
+		);
+		assert!(supervisor.result().is_ok());
+	}
+}
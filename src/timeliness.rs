@@ -0,0 +1,304 @@
+// MIT License
+//
+// Copyright (c) 2019 Ankur Srivastava
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use crate::{
+	generated::{BatchMessage, Message},
+	metrics::Registry,
+};
+use serde::Deserialize;
+use std::collections::{hash_map::Entry, BTreeMap, HashMap};
+use tokio::time::Duration;
+
+/// Number of points older than the lateness threshold, labelled with the
+/// action taken.
+pub const LATE_POINTS_METRIC: &str = "sink-late-points";
+
+/// Number of points further in the future than the skew tolerance, labelled
+/// with the action taken.
+pub const FUTURE_POINTS_METRIC: &str = "sink-future-points";
+
+/// What the subscriber does with a point outside of the accepted time range.
+#[derive(Deserialize, Debug, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum TimePolicy {
+	/// Write it as it is.
+	#[default]
+	Accept,
+
+	/// Don't write it at all.
+	Drop,
+
+	/// Move its timestamp to the closest accepted one.
+	Clamp,
+
+	/// Write it to the out_of_range_metrics table instead.
+	Route,
+}
+
+impl TimePolicy {
+	fn as_str(&self) -> &'static str {
+		match self {
+			TimePolicy::Accept => "accept",
+			TimePolicy::Drop => "drop",
+			TimePolicy::Clamp => "clamp",
+			TimePolicy::Route => "route",
+		}
+	}
+}
+
+/// Why a point is outside of the accepted time range.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OutOfRange {
+	Late,
+	Future,
+}
+
+impl OutOfRange {
+	/// Reason stored along with routed points.
+	pub fn as_str(&self) -> &'static str {
+		match self {
+			OutOfRange::Late => "late",
+			OutOfRange::Future => "future",
+		}
+	}
+}
+
+/// Accepted time range of the points, relative to the time they are
+/// received, and the policies for the points outside of it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Timeliness {
+	/// Points older than this are late.
+	pub max_age: Duration,
+	pub late: TimePolicy,
+	/// Points further ahead than this are from the future.
+	pub max_skew: Duration,
+	pub future: TimePolicy,
+}
+
+impl Default for Timeliness {
+	fn default() -> Self {
+		Timeliness {
+			max_age: Duration::from_secs(7 * 24 * 3600),
+			late: TimePolicy::Accept,
+			max_skew: Duration::from_secs(300),
+			future: TimePolicy::Accept,
+		}
+	}
+}
+
+impl Timeliness {
+	/// Apply the policies to the points of `batch`, which is received at
+	/// `now` in milliseconds since the epoch, like the timestamps of the
+	/// points. Returns the points to route, they are removed from the batch.
+	///
+	/// Every point outside of the range is counted in `metrics`. Clamped
+	/// points of a series would share their timestamp, so only the latest of
+	/// them is kept.
+	///
+	/// # Examples
+	/// Basic usage:
+	///
+	/// ```rust norun
+	/// let routed = timeliness.apply(&mut batch_message, Utc::now().timestamp_millis(), &metrics);
+	/// ```
+	pub fn apply(
+		&self,
+		batch: &mut BatchMessage,
+		now: i64,
+		metrics: &Registry,
+	) -> Vec<(OutOfRange, Message)> {
+		let oldest = now.saturating_sub(self.max_age.as_millis() as i64);
+		let newest = now.saturating_add(self.max_skew.as_millis() as i64);
+
+		let mut routed = vec![];
+		let mut kept = Vec::with_capacity(batch.multiple_points.len());
+		// Original timestamp and index in `kept` of the latest clamped point
+		// of each series and bound.
+		let mut clamped = HashMap::new();
+		let mut superseded = vec![];
+		for mut point in batch.multiple_points.drain(..) {
+			let (out_of_range, policy, bound, metric) = if point.timestamp < oldest {
+				(OutOfRange::Late, self.late, oldest, LATE_POINTS_METRIC)
+			} else if point.timestamp > newest {
+				(
+					OutOfRange::Future,
+					self.future,
+					newest,
+					FUTURE_POINTS_METRIC,
+				)
+			} else {
+				kept.push(point);
+				continue;
+			};
+			metrics.increment(metric, &[("action", policy.as_str())], 1.0);
+			match policy {
+				TimePolicy::Accept => kept.push(point),
+				TimePolicy::Drop => {}
+				TimePolicy::Clamp => {
+					let latest = (point.timestamp, kept.len());
+					match clamped.entry((bound, series(&point))) {
+						Entry::Vacant(entry) => {
+							entry.insert(latest);
+						}
+						Entry::Occupied(entry) if entry.get().0 > latest.0 => {
+							superseded.push(latest.1)
+						}
+						Entry::Occupied(mut entry) => superseded.push(entry.insert(latest).1),
+					}
+					point.timestamp = bound;
+					kept.push(point);
+				}
+				TimePolicy::Route => routed.push((out_of_range, point)),
+			}
+		}
+		batch.multiple_points = kept
+			.into_iter()
+			.enumerate()
+			.filter(|(index, _)| !superseded.contains(index))
+			.map(|(_, point)| point)
+			.collect();
+		routed
+	}
+}
+
+/// Name and labels of a point, in a form which can be compared and hashed.
+fn series(point: &Message) -> (String, Vec<(String, String)>) {
+	let labels: BTreeMap<_, _> = point.labels.clone().into_iter().collect();
+	(point.name.clone(), labels.into_iter().collect())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn point(name: &str, timestamp: i64) -> Message {
+		Message {
+			timestamp,
+			name: name.to_string(),
+			..Default::default()
+		}
+	}
+
+	#[test]
+	fn test_timeliness() {
+		// Milliseconds, like the timestamps of the generated metrics.
+		let now = 1_666_000_000_000;
+		let timeliness = Timeliness {
+			max_age: Duration::from_secs(3600),
+			late: TimePolicy::Route,
+			max_skew: Duration::from_secs(60),
+			future: TimePolicy::Clamp,
+		};
+		let metrics = Registry::default();
+		let mut batch = BatchMessage {
+			multiple_points: vec![
+				point("on-time", now - 10_000),
+				point("from-1970", 0),
+				point("ahead", now + 3_600_000),
+			],
+		};
+		let routed = timeliness.apply(&mut batch, now, &metrics);
+
+		assert_eq!(routed, vec![(OutOfRange::Late, point("from-1970", 0))]);
+		assert_eq!(
+			batch.multiple_points,
+			vec![point("on-time", now - 10_000), point("ahead", now + 60_000)]
+		);
+		assert_eq!(
+			metrics.get(LATE_POINTS_METRIC, &[("action", "route")]),
+			Some(1.0)
+		);
+		assert_eq!(
+			metrics.get(FUTURE_POINTS_METRIC, &[("action", "clamp")]),
+			Some(1.0)
+		);
+
+		let dropping = Timeliness {
+			late: TimePolicy::Drop,
+			future: TimePolicy::Accept,
+			..timeliness
+		};
+		let mut batch = BatchMessage {
+			multiple_points: vec![point("from-1970", 0), point("ahead", now + 3_600_000)],
+		};
+		assert!(dropping.apply(&mut batch, now, &metrics).is_empty());
+		assert_eq!(batch.multiple_points, vec![point("ahead", now + 3_600_000)]);
+
+		// A point received a second late is neither late nor from the future.
+		let mut batch = BatchMessage {
+			multiple_points: vec![point("just-now", now - 1_000)],
+		};
+		assert!(timeliness.apply(&mut batch, now, &metrics).is_empty());
+		assert_eq!(batch.multiple_points, vec![point("just-now", now - 1_000)]);
+		assert_eq!(
+			metrics.get(FUTURE_POINTS_METRIC, &[("action", "clamp")]),
+			Some(1.0)
+		);
+	}
+
+	#[test]
+	fn test_clamp_keeps_latest_point_of_series() {
+		let now = 1_666_000_000_000;
+		let timeliness = Timeliness {
+			future: TimePolicy::Clamp,
+			..Default::default()
+		};
+		let metrics = Registry::default();
+		let mut labelled = point("ahead", now + 7_200_000);
+		labelled.labels.insert("host".to_string(), "a".to_string());
+		let mut batch = BatchMessage {
+			multiple_points: vec![
+				Message {
+					value: 2.0,
+					..point("ahead", now + 7_200_000)
+				},
+				Message {
+					value: 1.0,
+					..point("ahead", now + 3_600_000)
+				},
+				point("on-time", now),
+				labelled.clone(),
+			],
+		};
+		assert!(timeliness.apply(&mut batch, now, &metrics).is_empty());
+
+		// Both unlabelled points end up at the same timestamp, the later one
+		// wins. The labelled point is another series.
+		let newest = now + 300_000;
+		labelled.timestamp = newest;
+		assert_eq!(
+			batch.multiple_points,
+			vec![
+				Message {
+					value: 2.0,
+					..point("ahead", newest)
+				},
+				point("on-time", now),
+				labelled,
+			]
+		);
+		assert_eq!(
+			metrics.get(FUTURE_POINTS_METRIC, &[("action", "clamp")]),
+			Some(3.0)
+		);
+	}
+}